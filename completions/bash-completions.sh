@@ -142,12 +142,16 @@ _anek() {
 
     case "${cmd}" in
         anek)
-            opts="-q -h -V --quiet --help --version new variable list edit export run render completions report view show graph help"
+            opts="-q -h -V --quiet --safe --unsafe --strict --input-precedence --help --version new variable list edit export run render completions report view show graph help"
             if [[ ${cur} == -* || ${COMP_CWORD} -eq 1 ]] ; then
                 COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
                 return 0
             fi
             case "${prev}" in
+                --input-precedence)
+                    COMPREPLY=($(compgen -W "inner outer" -- "${cur}"))
+                    return 0
+                    ;;
                 *)
                     COMPREPLY=()
                     ;;
@@ -256,7 +260,7 @@ _anek() {
             return 0
             ;;
         anek____on)
-            opts="-s -b -l -i -o -h --select-inputs --batch --loop --input --overwrite --help [COMMAND_ARGS]..."
+            opts="-s -b -l -i -o -h --select-inputs --batch --loop --input --vary --overwrite --help [COMMAND_ARGS]..."
             if [[ ${cur} == -* || ${COMP_CWORD} -eq 3 ]] ; then
                 COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
                 return 0
@@ -294,6 +298,10 @@ _anek() {
                     COMPREPLY=($(compgen -W "$(anek -q list -i)" -- "${cur}"))
                     return 0
                     ;;
+                --vary)
+                    COMPREPLY=($(compgen -W "$(anek -q list -v)" -- "${cur}"))
+                    return 0
+                    ;;
                 --overwrite)
                     COMPREPLY=($(compgen -W "$(anek -q list -v)" -- "${cur}"))
                     return 0
@@ -652,7 +660,7 @@ _anek() {
             return 0
             ;;
         anek__run)
-            opts="-t -p -P -d -h --template --pipeline --pipable --demo --help <COMMAND> [PATH] on help"
+            opts="-t -p -P -d -j -k -L -r -f -e -Q -h --template --pipeline --pipable --demo --jobs --stream --keep-going --log --resume --rerun-failed --force --timeout --retry --env --env-prefix --shell --quote --pipe --from-step --to-step --only-step --help <COMMAND> [PATH] on help"
             if [[ ${cur} == -* ]] ; then
                 COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
                 return 0
@@ -710,7 +718,7 @@ _anek() {
             return 0
             ;;
         anek__show)
-            opts="-h --help <ANEK_FILE> [PATH]"
+            opts="-r -h --resolved --help <ANEK_FILE> [PATH]"
             if [[ ${cur} == -* || ${COMP_CWORD} -eq 2 ]] ; then
                 COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
                 return 0
@@ -724,7 +732,7 @@ _anek() {
             return 0
             ;;
         anek__variable)
-            opts="-s -S -a -l -d -i -u -h --scan-inputs --scan-commands --add --list --details --info --update --help [PATH]"
+            opts="-s -S -a -l -d -i -e -I -u -h --scan-inputs --scan-commands --add --list --details --info --explain --input --update --help [PATH]"
             if [[ ${cur} == -* || ${COMP_CWORD} -eq 2 ]] ; then
                 COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
                 return 0
@@ -738,6 +746,22 @@ _anek() {
                     COMPREPLY=($(compgen -f "${cur}"))
                     return 0
                     ;;
+                --explain)
+                    COMPREPLY=($(compgen -W "$(anek -q list -v)" -- "${cur}"))
+                    return 0
+                    ;;
+                -e)
+                    COMPREPLY=($(compgen -W "$(anek -q list -v)" -- "${cur}"))
+                    return 0
+                    ;;
+                --input)
+                    COMPREPLY=($(compgen -W "$(anek -q list -i)" -- "${cur}"))
+                    return 0
+                    ;;
+                -I)
+                    COMPREPLY=($(compgen -W "$(anek -q list -i)" -- "${cur}"))
+                    return 0
+                    ;;
                 --update)
                     COMPREPLY=($(compgen -f "${cur}"))
                     return 0
@@ -1,12 +1,20 @@
-use anyhow::Error;
+use anyhow::{Context, Error};
 use colored::Colorize;
 use core::slice::Iter;
 use itertools::Itertools;
-use std::{collections::HashMap, fs, path::PathBuf};
-use string_template_plus::{Render, RenderOptions, Template};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+use string_template_plus::{Render, RenderOptions, Template, TemplatePart};
 use subprocess::Exec;
 
-use crate::variable;
+use crate::jobs::{self, JobOutput, OutputMode, StepStatus};
+use crate::variable::{self, InputOptions};
 
 #[derive(Clone)]
 pub enum AnekDirectoryType {
@@ -76,55 +84,856 @@ pub struct AnekDirectory {
     pub root: PathBuf,
 }
 
+/// Safe mode, the shell commands (`$(...)`) in the templates are not run
+static SAFE_MODE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_safe_mode(safe: bool) {
+    SAFE_MODE.store(safe, Ordering::SeqCst);
+}
+
+pub fn safe_mode() -> bool {
+    SAFE_MODE.load(Ordering::SeqCst)
+}
+
+/// Render options with the given variables, the shell commands are
+/// only allowed when not in safe mode
+pub fn render_options(variables: HashMap<String, String>) -> RenderOptions {
+    RenderOptions {
+        wd: PathBuf::from("."),
+        variables,
+        shell_commands: !safe_mode(),
+    }
+}
+
+/// Error if the template needs to run shell commands in safe mode
+pub fn check_safe(templ: &Template) -> Result<(), Error> {
+    fn has_cmd(parts: &[TemplatePart]) -> bool {
+        parts.iter().any(|p| match p {
+            TemplatePart::Cmd(_) => true,
+            TemplatePart::Any(parts) => has_cmd(parts),
+            _ => false,
+        })
+    }
+    if safe_mode() && has_cmd(templ.parts()) {
+        return Err(Error::msg(format!(
+            "Template {:?} runs shell commands with $(...), which are disabled in safe mode (use --unsafe if you trust it)",
+            templ.original()
+        )));
+    }
+    Ok(())
+}
+
+/// Render the template, see [`check_safe`]
+pub fn render_template(templ: &Template, op: &RenderOptions) -> Result<String, Error> {
+    check_safe(templ)?;
+    templ.render(op)
+}
+
+/// Prefix for the metadata lines in the command files
+///
+/// Lines like `#@output=out/{name}.csv` are not part of the command
+/// template, but declare information about the command.
+pub static COMMAND_META_PREFIX: &str = "#@";
+
+/// Metadata of a command, declared in the command file
+#[derive(Default)]
+pub struct CommandMeta {
+    /// Files the command reads, templates rendered with the job variables
+    pub inputs: Vec<Template>,
+    /// Files the command makes, templates rendered with the job variables
+    pub outputs: Vec<Template>,
+    /// Kill the command if it runs longer than this
+    pub timeout: Option<Duration>,
+    /// Number of times to retry the command if it fails
+    pub retry: Option<usize>,
+    /// Export the variables as environment variables or not
+    pub env: Option<bool>,
+    /// Prefix for the exported variables
+    pub env_prefix: Option<String>,
+    /// Shell to run the command in
+    pub shell: Option<Shell>,
+    /// Quote the substituted values or not
+    pub quote: Option<bool>,
+}
+
+impl CommandMeta {
+    fn insert(&mut self, key: &str, value: &str) -> Result<(), Error> {
+        match key {
+            "input" => self.inputs.push(Template::parse_template(value)?),
+            "output" => self.outputs.push(Template::parse_template(value)?),
+            "timeout" => self.timeout = Some(jobs::parse_duration(value)?),
+            "retry" => self.retry = Some(value.parse()?),
+            "env" => self.env = Some(value.parse()?),
+            "env-prefix" => self.env_prefix = Some(value.to_string()),
+            "shell" => self.shell = Some(Shell::parse(value)?),
+            "quote" => self.quote = Some(value.parse()?),
+            _ => return Err(Error::msg(format!("Unknown command metadata: {:?}", key))),
+        }
+        Ok(())
+    }
+}
+
+/// Options to run the commands with, some of them can be overridden
+/// by the command metadata
+pub struct RunOptions {
+    pub demo: bool,
+    pub pipable: bool,
+    /// Run the commands even if their outputs are up to date
+    pub force: bool,
+    pub timeout: Option<Duration>,
+    pub retry: usize,
+    /// Export the variables as environment variables
+    pub env: bool,
+    pub env_prefix: String,
+    pub shell: Shell,
+    /// Quote the substituted values for the shell
+    pub quote: bool,
+    pub root: PathBuf,
+}
+
 pub struct Command {
     name: String,
     templ: Template,
+    meta: CommandMeta,
 }
 
 impl Command {
     pub fn new<T: ToString>(name: T, cmd: T) -> Result<Self, Error> {
+        let name = name.to_string();
+        let mut meta = CommandMeta::default();
+        let mut lines: Vec<&str> = Vec::new();
+        let cmd = cmd.to_string();
+        for line in cmd.lines() {
+            if let Some(m) = line.trim_start().strip_prefix(COMMAND_META_PREFIX) {
+                let (key, value) = m
+                    .split_once('=')
+                    .context(format!("Invalid metadata in command {}: {:?}", name, line))?;
+                meta.insert(key.trim(), value.trim())?;
+            } else {
+                lines.push(line);
+            }
+        }
         Ok(Self {
-            name: name.to_string(),
-            templ: Template::parse_template(&cmd.to_string())?,
+            templ: Template::parse_template(lines.join("\n").trim())?,
+            name,
+            meta,
         })
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     pub fn template(&self) -> &Template {
         &self.templ
     }
 
-    pub fn print(&self, rendered_cmd: &str) {
-        eprint!("{} ({}): ", "Command".bright_green(), self.name);
-        println!("{}", rendered_cmd);
-        eprintln!("⇒");
+    pub fn print(&self, rendered_cmd: &str, output: &JobOutput) {
+        let label = format!("{} ({}): ", "Command".bright_green(), self.name);
+        if output.mode() == OutputMode::Prefix {
+            // the prefixed lines are only printed once complete, so a
+            // label split over two streams would come after the command
+            output.err(&format!("{}{}\n", label, rendered_cmd));
+        } else {
+            output.err(&label);
+            output.out(&format!("{}\n", rendered_cmd));
+        }
+        output.err("⇒\n");
+        output.log(&format!("Command ({}): {}\n", self.name, rendered_cmd));
     }
 
-    pub fn render(&self, variables: HashMap<String, String>) -> Result<String, Error> {
-        let op = RenderOptions {
-            wd: PathBuf::from("."),
-            variables,
-            shell_commands: true,
+    /// Render the command, quoting the substituted values if `quoting` is given
+    pub fn render(
+        &self,
+        variables: HashMap<String, String>,
+        quoting: Option<Quoting>,
+    ) -> Result<String, Error> {
+        check_safe(&self.templ)?;
+        render_parts(self.templ.parts(), &render_options(variables), quoting)
+    }
+
+    pub fn meta(&self) -> &CommandMeta {
+        &self.meta
+    }
+
+    pub fn has_outputs(&self) -> bool {
+        !self.meta.outputs.is_empty()
+    }
+
+    /// Check if the declared outputs are newer than the declared inputs
+    ///
+    /// Commands without any outputs are never up to date, same as
+    /// the ones with missing outputs or inputs.
+    pub fn up_to_date(&self, variables: &HashMap<String, String>) -> Result<bool, Error> {
+        if self.meta.outputs.is_empty() {
+            return Ok(false);
+        }
+        let op = render_options(variables.clone());
+        let mtimes = |templs: &Vec<Template>| -> Result<Option<Vec<SystemTime>>, Error> {
+            Ok(templs
+                .iter()
+                .map(|t| render_template(t, &op))
+                .collect::<Result<Vec<String>, Error>>()?
+                .iter()
+                .map(|p| fs::metadata(p).and_then(|m| m.modified()).ok())
+                .collect())
         };
-        self.templ.render(&op)
+        let oldest_output = match mtimes(&self.meta.outputs)? {
+            Some(times) => times.into_iter().min(),
+            None => return Ok(false),
+        };
+        let newest_input = match mtimes(&self.meta.inputs)? {
+            Some(times) => times.into_iter().max(),
+            None => return Ok(false),
+        };
+        Ok(newest_input <= oldest_output)
     }
 
+    /// Environment variables for the command, empty if it doesn't export them
+    pub fn env(
+        &self,
+        input: &CommandInputs,
+        variables: &HashMap<String, String>,
+        opts: &RunOptions,
+    ) -> Vec<(String, String)> {
+        if !self.meta.env.unwrap_or(opts.env) {
+            return vec![];
+        }
+        let prefix = self.meta.env_prefix.as_ref().unwrap_or(&opts.env_prefix);
+        let mut env: Vec<(String, String)> = variables
+            .iter()
+            .map(|(k, v)| (format!("{}{}", prefix, k), v.to_string()))
+            .collect();
+        env.push(("ANEK_JOB_INDEX".to_string(), input.index().to_string()));
+        env.push(("ANEK_JOB_NAME".to_string(), input.name().to_string()));
+        env.push((
+            "ANEK_ROOT".to_string(),
+            opts.root.to_string_lossy().to_string(),
+        ));
+        env
+    }
+
+    /// Render the command, with the values quoted for its shell if needed
+    pub fn render_for_shell(
+        &self,
+        variables: &HashMap<String, String>,
+        opts: &RunOptions,
+    ) -> Result<String, Error> {
+        let quoting = if !self.meta.quote.unwrap_or(opts.quote) {
+            None
+        } else if self.is_script() {
+            Some(Quoting::Posix)
+        } else {
+            Some(self.shell(opts).quoting())
+        };
+        self.render(variables.clone(), quoting)
+    }
+
+    fn is_script(&self) -> bool {
+        self.templ.original().starts_with("#!")
+    }
+
+    fn shell<'a>(&'a self, opts: &'a RunOptions) -> &'a Shell {
+        self.meta.shell.as_ref().unwrap_or(&opts.shell)
+    }
+
+    pub fn timeout(&self, opts: &RunOptions) -> Option<Duration> {
+        self.meta.timeout.or(opts.timeout)
+    }
+
+    /// Arguments to run the rendered command, scripts are saved in a
+    /// temporary file that is also returned, it is removed when dropped
+    pub fn argv(
+        &self,
+        cmd: String,
+        opts: &RunOptions,
+    ) -> Result<(Vec<String>, Option<ScriptFile>), Error> {
+        if self.is_script() {
+            let script = ScriptFile::write(&cmd)?;
+            Ok((
+                vec![script.path().to_string_lossy().to_string()],
+                Some(script),
+            ))
+        } else {
+            Ok((self.shell(opts).argv(cmd)?, None))
+        }
+    }
+
+    /// Run the command, its stdout is also saved in `capture` if given
     pub fn run(
         &self,
+        input: &CommandInputs,
         variables: &HashMap<String, String>,
-        demo: bool,
-        pipable: bool,
-    ) -> Result<(), Error> {
-        let cmd = self.render(variables.clone())?;
-        if pipable {
-            println!("{}", cmd);
+        opts: &RunOptions,
+        output: &JobOutput,
+        capture: Option<&mut String>,
+    ) -> Result<StepStatus, Error> {
+        let cmd = self.render_for_shell(variables, opts)?;
+        if opts.pipable {
+            output.out(&format!("{}\n", cmd));
+        } else {
+            self.print(&cmd, output);
+            if !opts.demo {
+                let env = self.env(input, variables, opts);
+                let (argv, _script) = self.argv(cmd, opts)?;
+                return output.run(&argv, &env, self.timeout(opts), capture);
+            }
+        }
+        Ok(StepStatus::Success)
+    }
+}
+
+/// Program used to run the rendered commands
+#[derive(Clone)]
+pub enum Shell {
+    /// Interpreter and its arguments, the command is passed as the
+    /// last argument (e.g. `sh -c`, `python -c`)
+    Interpreter(Vec<String>),
+    /// No shell, split the command into arguments and run it directly
+    Direct,
+}
+
+impl Default for Shell {
+    /// Same as the one used by [`subprocess::Exec::shell`]
+    fn default() -> Self {
+        if cfg!(windows) {
+            Self::Interpreter(vec!["cmd.exe".to_string(), "/C".to_string()])
+        } else {
+            Self::Interpreter(vec!["sh".to_string(), "-c".to_string()])
+        }
+    }
+}
+
+impl Shell {
+    /// Parse the shell from string like `bash`, `python -c` or `none`
+    ///
+    /// If only the program is given, `-c` is used as its argument.
+    pub fn parse(shell: &str) -> Result<Self, Error> {
+        let mut args = split_args(shell)?;
+        match args.len() {
+            0 => Err(Error::msg("Empty shell")),
+            1 if args[0] == "none" => Ok(Self::Direct),
+            1 => {
+                args.push("-c".to_string());
+                Ok(Self::Interpreter(args))
+            }
+            _ => Ok(Self::Interpreter(args)),
+        }
+    }
+
+    pub fn argv(&self, cmd: String) -> Result<Vec<String>, Error> {
+        match self {
+            Self::Interpreter(args) => {
+                let mut argv = args.clone();
+                argv.push(cmd);
+                Ok(argv)
+            }
+            Self::Direct => {
+                let argv = split_args(&cmd)?;
+                if argv.is_empty() {
+                    return Err(Error::msg("Empty command"));
+                }
+                Ok(argv)
+            }
+        }
+    }
+
+    /// Quoting rules of the shell, POSIX ones unless it is `fish` or `cmd`
+    pub fn quoting(&self) -> Quoting {
+        let program = match self {
+            Self::Interpreter(args) => PathBuf::from(&args[0]),
+            Self::Direct => return Quoting::Posix,
+        };
+        match program.file_stem().and_then(|s| s.to_str()) {
+            Some("fish") => Quoting::Fish,
+            Some("cmd") => Quoting::Cmd,
+            _ => Quoting::Posix,
+        }
+    }
+}
+
+/// Transformer that marks the variable to be used as it is, even when
+/// the values are quoted (e.g. `{flags:raw}`)
+pub static RAW_MARKER: &str = "raw";
+
+/// Quoting rules for the values substituted in the commands
+#[derive(Clone, Copy)]
+pub enum Quoting {
+    /// Single quotes, with `'\''` for the quote inside (sh, bash, zsh...)
+    Posix,
+    /// Single quotes, with backslash escapes for `'` and `\`
+    Fish,
+    /// Double quotes, with `""` for the quote inside (Windows cmd)
+    Cmd,
+}
+
+impl Quoting {
+    /// Quote the value so the shell sees it as a single word, values
+    /// with only the safe characters are left as they are
+    pub fn quote(&self, value: &str) -> String {
+        if !value.is_empty()
+            && value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c))
+        {
+            return value.to_string();
+        }
+        match self {
+            Self::Posix => format!("'{}'", value.replace('\'', "'\\''")),
+            Self::Fish => format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'")),
+            Self::Cmd => format!("\"{}\"", value.replace('"', "\"\"")),
+        }
+    }
+}
+
+/// Remove the [`RAW_MARKER`] from the template part, and tell if it had one
+fn strip_raw(part: &TemplatePart) -> (TemplatePart, bool) {
+    let strip = |transformers: &str| -> (String, bool) {
+        let (raw, rest): (Vec<&str>, Vec<&str>) =
+            transformers.split(':').partition(|t| *t == RAW_MARKER);
+        (rest.join(":"), !raw.is_empty())
+    };
+    match part {
+        TemplatePart::Var(v, f) => {
+            let (f, raw) = strip(f);
+            (TemplatePart::Var(v.clone(), f), raw)
+        }
+        TemplatePart::Lisp(e, f, vars) => {
+            let (f, raw) = strip(f);
+            (TemplatePart::Lisp(e.clone(), f, vars.clone()), raw)
+        }
+        TemplatePart::Any(parts) => {
+            let (parts, raw): (Vec<TemplatePart>, Vec<bool>) = parts.iter().map(strip_raw).unzip();
+            (TemplatePart::Any(parts), raw.contains(&true))
+        }
+        TemplatePart::Cmd(parts) => (
+            TemplatePart::Cmd(parts.iter().map(|p| strip_raw(p).0).collect()),
+            false,
+        ),
+        p => (p.clone(), false),
+    }
+}
+
+/// Render the template parts, quoting everything except the literal
+/// parts and the values marked with [`RAW_MARKER`]
+///
+/// The commands inside `$(...)` are quoted for `sh` as that's what
+/// runs them, and their output is quoted as a single value.
+fn render_parts(
+    parts: &[TemplatePart],
+    op: &RenderOptions,
+    quoting: Option<Quoting>,
+) -> Result<String, Error> {
+    let mut rendered = String::new();
+    for part in parts {
+        let (part, raw) = strip_raw(part);
+        let quoting = match (&part, quoting) {
+            (TemplatePart::Lit(_), _) | (_, None) => {
+                rendered.push_str(&part.render(op)?);
+                continue;
+            }
+            (_, Some(q)) => q,
+        };
+        let value = match &part {
+            TemplatePart::Cmd(cmd) => {
+                let cmd = render_parts(cmd, op, Some(Quoting::Posix))?;
+                if !op.shell_commands {
+                    rendered.push_str(&format!("$({})", cmd));
+                    continue;
+                }
+                let out = Exec::shell(cmd).cwd(&op.wd).capture()?.stdout_str();
+                out.trim_end_matches('\n').to_string()
+            }
+            p => p.render(op)?,
+        };
+        if raw {
+            rendered.push_str(&value);
+        } else {
+            rendered.push_str(&quoting.quote(&value));
+        }
+    }
+    Ok(rendered)
+}
+
+/// Split the string into arguments like a POSIX shell would
+///
+/// Only handles the quotes and backslash escapes, there are no
+/// expansions of any kind.
+pub fn split_args(s: &str) -> Result<Vec<String>, Error> {
+    let mut args = Vec::new();
+    let mut arg: Option<String> = None;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if let Some(a) = arg.take() {
+                    args.push(a);
+                }
+            }
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    arg.get_or_insert_with(String::new).push(c);
+                }
+            }
+            '\'' => {
+                let a = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => a.push(c),
+                        None => return Err(Error::msg(format!("Unclosed quote in: {}", s))),
+                    }
+                }
+            }
+            '"' => {
+                let a = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) if "\\\"$`".contains(c) => a.push(c),
+                            Some(c) => {
+                                a.push('\\');
+                                a.push(c);
+                            }
+                            None => return Err(Error::msg(format!("Unclosed quote in: {}", s))),
+                        },
+                        Some(c) => a.push(c),
+                        None => return Err(Error::msg(format!("Unclosed quote in: {}", s))),
+                    }
+                }
+            }
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if let Some(a) = arg {
+        args.push(a);
+    }
+    Ok(args)
+}
+
+/// Script saved in a temporary executable file, inside its own
+/// private directory that is removed when this is dropped
+pub struct ScriptFile {
+    dir: PathBuf,
+    path: PathBuf,
+}
+
+impl ScriptFile {
+    fn write(script: &str) -> Result<Self, Error> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let dir = loop {
+            let dir = std::env::temp_dir().join(format!(
+                "anek-{}-{}-{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::SeqCst),
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.subsec_nanos())
+            ));
+            let mut builder = fs::DirBuilder::new();
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::DirBuilderExt;
+                builder.mode(0o700);
+            }
+            // never reuse a directory someone else made
+            match builder.create(&dir) {
+                Ok(()) => break dir,
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e.into()),
+            }
+        };
+        // removes the directory even if writing fails
+        let script_file = Self {
+            path: dir.join("script"),
+            dir,
+        };
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o700);
+        }
+        let mut file = options.open(&script_file.path)?;
+        writeln!(file, "{}", script)?;
+        Ok(script_file)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ScriptFile {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.dir).ok();
+    }
+}
+
+/// Prefix for the options of the steps in the pipeline files
+pub static STEP_OPTION_PREFIX: &str = "@";
+
+/// How to save the output of a pipeline step into variables
+pub enum Capture {
+    /// Whole stdout (without the trailing newlines) into the variable
+    Whole(String),
+    /// Each `var=value` line of the stdout into its variable
+    Variables,
+}
+
+/// Condition for running a pipeline step, a template that is true if
+/// it can be rendered and isn't empty, `0`, `false` or `F` (false in
+/// the lisp expressions); or compared to a value with `==` or `!=`
+pub struct Condition {
+    templ: Template,
+    /// Value to compare to, and whether it should be equal
+    compare: Option<(String, bool)>,
+}
+
+impl Condition {
+    pub fn parse(cond: &str) -> Result<Self, Error> {
+        let (templ, compare) = if let Some((t, v)) = cond.split_once("!=") {
+            (t, Some((v.to_string(), false)))
+        } else if let Some((t, v)) = cond.split_once("==") {
+            (t, Some((v.to_string(), true)))
+        } else {
+            (cond, None)
+        };
+        Ok(Self {
+            templ: Template::parse_template(templ)?,
+            compare,
+        })
+    }
+
+    pub fn check(&self, variables: &HashMap<String, String>) -> Result<bool, Error> {
+        check_safe(&self.templ)?;
+        // lisp expressions panic on missing variables instead of failing
+        let missing = self
+            .templ
+            .parts()
+            .iter()
+            .filter(|p| matches!(p, TemplatePart::Lisp(..)))
+            .flat_map(|p| p.variables())
+            .any(|v| !variables.contains_key(v));
+        let value = if missing {
+            None
         } else {
-            self.print(&cmd);
-            if !demo {
-                Exec::shell(cmd).join()?;
+            self.templ.render(&render_options(variables.clone())).ok()
+        };
+        Ok(match (value, &self.compare) {
+            (None, _) => false,
+            (Some(v), Some((val, eq))) => (&v == val) == *eq,
+            (Some(v), None) => !(v.is_empty() || v == "0" || v == "false" || v == "F"),
+        })
+    }
+}
+
+impl std::fmt::Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.templ.original())?;
+        if let Some((val, eq)) = &self.compare {
+            write!(f, "{}{}", if *eq { "==" } else { "!=" }, val)?;
+        }
+        Ok(())
+    }
+}
+
+/// Command in a pipeline, with the variables to overwrite for it
+pub struct PipelineStep {
+    pub command: Command,
+    /// Overwrite for this step only, on top of the job's variables
+    pub overwrite: HashMap<String, String>,
+    /// Save the output into the job's variables for the later steps
+    pub capture: Option<Capture>,
+    /// Don't stop the job if this step fails
+    pub allow_failure: bool,
+    /// Run this step even if an earlier step failed
+    pub always: bool,
+    /// Only run this step if the condition is true
+    pub condition: Option<Condition>,
+    /// Name used by the other steps to depend on this one
+    pub id: Option<String>,
+    /// Steps (by their ids) that need to be done before this one
+    pub after: Vec<String>,
+}
+
+impl PipelineStep {
+    pub fn new(command: Command) -> Self {
+        Self {
+            command,
+            overwrite: HashMap::new(),
+            capture: None,
+            allow_failure: false,
+            always: false,
+            condition: None,
+            id: None,
+            after: Vec::new(),
+        }
+    }
+
+    /// Set the option from the pipeline file, like `@capture=var`
+    pub fn set_option(&mut self, option: &str) -> Result<(), Error> {
+        let opt = option.strip_prefix(STEP_OPTION_PREFIX).unwrap_or(option);
+        let (key, value) = match opt.split_once('=') {
+            Some((k, v)) => (k, Some(v)),
+            None => (opt, None),
+        };
+        match (key, value) {
+            ("capture", Some(var)) => self.capture = Some(Capture::Whole(var.to_string())),
+            ("capture", None) => self.capture = Some(Capture::Variables),
+            ("allow-failure", None) => self.allow_failure = true,
+            ("always", None) => self.always = true,
+            ("if", Some(cond)) => self.condition = Some(Condition::parse(cond)?),
+            ("id", Some(id)) => self.id = Some(id.to_string()),
+            ("after", Some(ids)) => self.after.extend(
+                ids.split(',')
+                    .map(|id| id.trim().to_string())
+                    .filter(|id| !id.is_empty()),
+            ),
+            _ => {
+                return Err(Error::msg(format!(
+                    "Unknown option for step {}: {:?}",
+                    self.command.name(),
+                    option
+                )))
             }
         }
         Ok(())
     }
+
+    /// Id of the step, the command name if it isn't given
+    pub fn id(&self) -> &str {
+        self.id.as_deref().unwrap_or(self.command.name())
+    }
+
+    /// Variables used by the command, its inputs and outputs, the
+    /// condition and the overwrite values
+    pub fn variables(&self) -> Vec<String> {
+        let meta = self.command.meta();
+        let mut parts: Vec<TemplatePart> = self.command.template().parts().clone();
+        for templ in meta.inputs.iter().chain(&meta.outputs) {
+            parts.extend(templ.parts().iter().cloned());
+        }
+        if let Some(cond) = &self.condition {
+            parts.extend(cond.templ.parts().iter().cloned());
+        }
+        for v in self.overwrite.values() {
+            if let Ok(templ) = Template::parse_template(v) {
+                parts.extend(templ.parts().iter().cloned());
+            }
+        }
+        parts
+            .iter()
+            .flat_map(|p| p.variables())
+            .map(|v| v.to_string())
+            .collect()
+    }
+}
+
+/// Steps of a pipeline, and how to run them
+#[derive(Default)]
+pub struct Pipeline {
+    pub steps: Vec<PipelineStep>,
+    /// Connect the steps with pipes, instead of running one after another
+    pub pipe: bool,
+}
+
+impl Pipeline {
+    /// Pipeline with only one command
+    pub fn single(command: Command) -> Self {
+        Self {
+            steps: vec![PipelineStep::new(command)],
+            pipe: false,
+        }
+    }
+
+    /// Set the option from the header of the pipeline file, like `@pipe`
+    pub fn set_header(&mut self, option: &str) -> Result<(), Error> {
+        match option.strip_prefix(STEP_OPTION_PREFIX).unwrap_or(option) {
+            "pipe" => self.pipe = true,
+            _ => return Err(Error::msg(format!("Unknown pipeline header: {:?}", option))),
+        }
+        Ok(())
+    }
+}
+
+/// File in the loop directory that configures how its variables are
+/// combined, instead of being a variable itself
+pub static LOOP_CONFIG_FILE: &str = ".loop";
+
+/// How the variables of a loop are combined, the ones not zipped
+/// together are combined in all possible ways
+#[derive(Default)]
+pub struct LoopConfig {
+    /// Groups of variables whose values are paired line by line
+    pub zip: Vec<Vec<String>>,
+    /// Only keep the combinations where all these are true
+    pub include: Vec<Condition>,
+    /// Skip the combinations where any of these is true
+    pub exclude: Vec<Condition>,
+}
+
+impl LoopConfig {
+    /// Read the config in the loop directory, if there is one
+    pub fn read(loop_dir: &Path) -> Result<Self, Error> {
+        let mut config = Self::default();
+        let path = loop_dir.join(LOOP_CONFIG_FILE);
+        if !path.exists() {
+            return Ok(config);
+        }
+        for (i, line) in variable::input_lines(&path, None)? {
+            let (key, value) = line.split_once(char::is_whitespace).unwrap_or((&line, ""));
+            let value = value.trim();
+            match key {
+                "zip" => {
+                    let vars: Vec<String> = value.split_whitespace().map(String::from).collect();
+                    if vars.len() < 2 {
+                        return Err(Error::msg(format!(
+                            "Need at least two variables to zip in {:?} line# {}",
+                            path, i
+                        )));
+                    }
+                    config.zip.push(vars);
+                }
+                "include" => config.include.push(Condition::parse(value)?),
+                "exclude" => config.exclude.push(Condition::parse(value)?),
+                _ => {
+                    return Err(Error::msg(format!(
+                        "Unknown loop config {:?} in {:?} line# {}",
+                        key, path, i
+                    )))
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    /// Variables used by the include and exclude conditions
+    pub fn variables(&self) -> HashSet<&str> {
+        self.include
+            .iter()
+            .chain(&self.exclude)
+            .flat_map(|c| c.templ.parts())
+            .flat_map(|p| p.variables())
+            .collect()
+    }
+
+    /// Whether the combination of the variables is to be run
+    pub fn keep(&self, variables: &HashMap<String, String>) -> Result<bool, Error> {
+        for cond in &self.include {
+            if !cond.check(variables)? {
+                return Ok(false);
+            }
+        }
+        for cond in &self.exclude {
+            if cond.check(variables)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
 }
 
 pub struct CommandInputs {
@@ -132,6 +941,11 @@ pub struct CommandInputs {
     name: String,
     files: Vec<PathBuf>,
     variables: HashMap<String, String>,
+    /// Overwrite values that are already rendered (for the loops)
+    overwrite: HashMap<String, String>,
+    /// Variables from the input files, their values can use other
+    /// variables and are resolved for the job
+    unresolved: HashSet<String>,
 }
 
 impl CommandInputs {
@@ -141,6 +955,8 @@ impl CommandInputs {
             name,
             files,
             variables: HashMap::new(),
+            overwrite: HashMap::new(),
+            unresolved: HashSet::new(),
         }
     }
 
@@ -150,12 +966,46 @@ impl CommandInputs {
             name,
             files: vec![],
             variables,
+            overwrite: HashMap::new(),
+            unresolved: HashSet::new(),
         }
     }
 
-    pub fn read_files(mut self) -> Result<Self, Error> {
-        let lines = variable::compact_lines_from_anek_file(self.files())?;
+    pub fn with_overwrite(mut self, overwrite: HashMap<String, String>) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// Inputs with the variables of both, the ones from `other`
+    /// replace the ones from `self`
+    pub fn combine(&self, index: usize, other: &CommandInputs) -> Self {
+        let mut variables = self.variables.clone();
+        variables.extend(other.variables.clone());
+        let mut overwrite = self.overwrite.clone();
+        overwrite.extend(other.overwrite.clone());
+        let unresolved = self
+            .unresolved
+            .iter()
+            .filter(|v| !other.variables.contains_key(*v))
+            .chain(other.unresolved.iter())
+            .cloned()
+            .collect();
+        Self {
+            index,
+            name: format!("{}; {}", self.name, other.name),
+            files: self.files.clone(),
+            variables,
+            overwrite,
+            unresolved,
+        }
+    }
+
+    pub fn read_files(mut self, opts: &InputOptions) -> Result<Self, Error> {
+        let sources = variable::input_sources(self.files(), opts)?;
+        variable::check_duplicates(&sources, opts)?;
+        let lines: Vec<(usize, String)> = sources.into_iter().map(|s| (s.line, s.text)).collect();
         variable::read_inputs(&lines, &mut self.variables)?;
+        self.unresolved = self.variables.keys().cloned().collect();
         Ok(self)
     }
 
@@ -167,15 +1017,35 @@ impl CommandInputs {
         &self.variables
     }
 
-    pub fn eprint_job(&self, job: usize, total: usize) {
-        eprintln!(
-            "{} {} [{} of {}]: {}",
+    pub fn overwrite(&self) -> &HashMap<String, String> {
+        &self.overwrite
+    }
+
+    pub fn unresolved(&self) -> &HashSet<String> {
+        &self.unresolved
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn job_header(&self, job: usize, total: usize) -> String {
+        format!(
+            "{} {} [{} of {}]: {}\n",
             "Job".bright_purple().bold(),
             self.index,
             job,
             total,
             self.name
-        );
+        )
+    }
+
+    pub fn eprint_job(&self, job: usize, total: usize) {
+        eprint!("{}", self.job_header(job, total));
     }
 }
 
@@ -226,6 +1096,25 @@ impl AnekDirectory {
         }
     }
 
+    /// Directory for the logs of the runs, it is not made by `anek new`
+    pub fn logs_dir(&self) -> PathBuf {
+        self.root.join("logs")
+    }
+
+    /// Settings for the anek directory from `.anek/config`
+    ///
+    /// The config file has `key=value` lines like the input files,
+    /// and it's fine if it doesn't exist.
+    pub fn config(&self) -> Result<HashMap<String, String>, Error> {
+        let mut config = HashMap::new();
+        let file = self.root.join("config");
+        if file.exists() {
+            let lines = variable::input_lines(&file, None)?;
+            variable::read_inputs(&lines, &mut config)?;
+        }
+        Ok(config)
+    }
+
     pub fn get_directory(&self, dirtype: &AnekDirectoryType) -> PathBuf {
         self.root.join(dirtype.dir_name())
     }
@@ -258,11 +1147,7 @@ impl AnekDirectory {
 
     pub fn command(&self, cmd: &str) -> Result<Command, Error> {
         let s = fs::read_to_string(self.get_file(&AnekDirectoryType::Commands, &cmd))?;
-        let templ = Template::parse_template(s.trim())?;
-        Ok(Command {
-            name: cmd.to_string(),
-            templ,
-        })
+        Command::new(cmd, s.trim())
     }
 
     pub fn inputs<T: ToString>(&self, index: usize, files: &Vec<T>) -> CommandInputs {
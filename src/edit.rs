@@ -15,7 +15,7 @@ pub struct CliArgs {
     #[arg(value_hint = ValueHint::Other)]
     anek_file: String,
     #[arg(default_value = ".", value_hint=ValueHint::DirPath)]
-    path: PathBuf,
+    pub path: PathBuf,
 }
 
 pub fn edit_file(args: CliArgs) -> Result<(), Error> {
@@ -1,13 +1,13 @@
 use anyhow::Error;
 use clap::{Args, ValueHint};
 use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
-use string_template_plus::{Render, RenderOptions, Template};
+use string_template_plus::Template;
 
-use crate::dtypes::AnekDirectory;
+use crate::dtypes::{self, AnekDirectory};
 use itertools::Itertools;
 
 use crate::run_utils;
+use crate::variable::InputOptions;
 
 #[derive(Args)]
 pub struct CliArgs {
@@ -107,19 +107,15 @@ impl ExportWrapers {
     }
 }
 
-pub fn run_command(args: CliArgs) -> Result<(), Error> {
+pub fn run_command(args: CliArgs, input_opts: &InputOptions) -> Result<(), Error> {
     let anek_dir = AnekDirectory::from_pwd()?;
 
     let wrappers = ExportWrapers::from_name(&args.format, &args.variables)?;
     let cmd_args = run_utils::command_args(&args.inputs);
     let overwrite: HashMap<String, String> = run_utils::overwrite_vars(&args.inputs, &cmd_args)?;
-    let input_files = run_utils::inputs(&anek_dir, &args.inputs, &HashSet::new())?;
+    let input_files = run_utils::inputs(&anek_dir, &args.inputs, &HashSet::new(), input_opts)?;
 
-    let mut renderop = RenderOptions {
-        wd: PathBuf::from("."),
-        variables: HashMap::new(),
-        shell_commands: false,
-    };
+    let mut renderop = dtypes::render_options(HashMap::new());
 
     print!("{}", wrappers.start);
     let total = input_files.len();
@@ -128,7 +124,10 @@ pub fn run_command(args: CliArgs) -> Result<(), Error> {
         print!("{}", wrappers.start_line);
         input.eprint_job(i, total);
         renderop.variables = run_utils::variables_from_input(input, &overwrite)?;
-        print!("{}", wrappers.vars_templ.render(&renderop)?);
+        print!(
+            "{}",
+            dtypes::render_template(&wrappers.vars_templ, &renderop)?
+        );
         print!("{}", wrappers.end_line);
         if i < total {
             print!("{}", wrappers.connector);
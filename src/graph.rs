@@ -5,7 +5,8 @@ use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 
 use crate::dtypes::{anekdirtype_iter, AnekDirectory, AnekDirectoryType};
-use crate::variable;
+use crate::run_utils::{self, PipelineEntry};
+use crate::variable::{self, InputOptions};
 
 #[derive(Args)]
 pub struct CliArgs {
@@ -28,7 +29,7 @@ pub struct CliArgs {
     #[arg(short, long, action)]
     urls: bool,
     #[arg(default_value = ".", value_hint=ValueHint::DirPath)]
-    path: PathBuf,
+    pub path: PathBuf,
 }
 
 lazy_static! {
@@ -41,7 +42,7 @@ lazy_static! {
     ]);
 }
 
-pub fn print_dot(args: CliArgs) -> Result<(), Error> {
+pub fn print_dot(args: CliArgs, input_opts: &InputOptions) -> Result<(), Error> {
     let filepath = AnekDirectory::from(&args.path)?;
     println!("digraph anek{{");
     println!("rank=LR;");
@@ -76,10 +77,33 @@ pub fn print_dot(args: CliArgs) -> Result<(), Error> {
         args.pipelines
     };
     for file in pipelines {
-        let lines = variable::input_lines(&dir.join(&file), None)?;
+        let lines = run_utils::pipeline_lines(&filepath, &file)?;
+        // same steps as the ones run, with the included pipelines
+        // expanded; the broken ones are only shown by their lines
+        let steps = run_utils::cmd_from_pipeline(&filepath, &file)
+            .map(|p| p.steps)
+            .unwrap_or_default();
+        if let Some(deps) = run_utils::step_dependencies(&steps)? {
+            // steps as a graph of their dependencies
+            for (i, step) in steps.iter().enumerate() {
+                for &d in &deps[i] {
+                    println!("\"{}\" -> \"{}\" [color=blue]", steps[d].id(), step.id());
+                }
+                if !deps.iter().any(|d| d.contains(&i)) {
+                    println!("\"{}\" -> \"{file}\" [color=blue]", step.id());
+                }
+            }
+            continue;
+        }
         let conn = lines
             .iter()
-            .map(|(_, s)| format!("\"{s}\""))
+            .filter_map(|l| match &l.entry {
+                PipelineEntry::Command(s, _) | PipelineEntry::Include(s) => {
+                    Some(format!("\"{s}\""))
+                }
+                PipelineEntry::Inline(_) => Some(format!("\"{file}:{}\"", l.number)),
+                PipelineEntry::Header => None,
+            })
             .collect::<Vec<String>>()
             .join(" -> ");
         println!("{conn} -> \"{file}\" [color=blue]");
@@ -98,11 +122,15 @@ pub fn print_dot(args: CliArgs) -> Result<(), Error> {
     let dir = filepath.get_directory(&AnekDirectoryType::Inputs);
     for file in variable::list_anek_filenames(&dir)? {
         let mut inputs: HashSet<&str> = HashSet::new();
-        let lines = variable::compact_lines_from_anek_file(&[dir.join(&file)].to_vec())?;
+        let lines = variable::compact_lines_from_anek_file(&vec![dir.join(&file)], input_opts)?;
         variable::read_inputs_set(&lines, &mut inputs)?;
         for input in inputs {
             println!("\"{input}\" -> \"{file}\"  [color=pink]");
         }
+        // inputs included in the input
+        for inc in variable::included_inputs(&vec![dir.join(&file)])? {
+            println!("\"{inc}\" -> \"{file}\"  [color=pink, style=dashed]");
+        }
     }
     // batch as sequence of inputs
     let dir = filepath.get_directory(&AnekDirectoryType::Batch);
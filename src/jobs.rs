@@ -0,0 +1,830 @@
+use anyhow::Error;
+use chrono::Local;
+use colored::{ColoredString, Colorize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use subprocess::{ExitStatus, Popen, PopenConfig, Redirection};
+
+/// Arguments of a command, and its environment variables
+pub type CommandArgs = (Vec<String>, Vec<(String, String)>);
+
+/// Status of a single command inside a job
+#[derive(Clone)]
+pub enum StepStatus {
+    Success,
+    /// The declared outputs are newer than the inputs, so it was not run
+    UpToDate,
+    Failed(ExitStatus),
+    /// The command was killed after running for the given duration
+    TimedOut(Duration),
+    /// The command was not run for the given reason
+    Skipped(String),
+    /// The job couldn't run the command, e.g. failed to render it
+    Error(String),
+}
+
+impl StepStatus {
+    pub fn from_exit(status: ExitStatus) -> Self {
+        if status.success() {
+            Self::Success
+        } else {
+            Self::Failed(status)
+        }
+    }
+
+    pub fn success(&self) -> bool {
+        matches!(self, Self::Success | Self::UpToDate | Self::Skipped(_))
+    }
+}
+
+impl std::fmt::Display for StepStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Success => write!(f, "success"),
+            Self::UpToDate => write!(f, "up to date"),
+            Self::Failed(ExitStatus::Exited(c)) => write!(f, "exit code {}", c),
+            Self::Failed(ExitStatus::Signaled(s)) => write!(f, "signal {}", s),
+            Self::Failed(ExitStatus::Other(c)) => write!(f, "status {}", c),
+            Self::Failed(ExitStatus::Undetermined) => write!(f, "unknown status"),
+            Self::TimedOut(d) => write!(f, "timed out after {:?}", d),
+            Self::Skipped(reason) => write!(f, "skipped ({})", reason),
+            Self::Error(e) => write!(f, "error: {}", e),
+        }
+    }
+}
+
+pub struct StepReport {
+    pub command: String,
+    pub status: StepStatus,
+    pub duration: Duration,
+    pub attempts: usize,
+    /// The job doesn't fail if this step fails
+    pub allow_failure: bool,
+}
+
+impl StepReport {
+    /// Report for the step that couldn't run its command
+    pub fn error(command: &str, error: &str) -> Self {
+        Self {
+            command: command.to_string(),
+            status: StepStatus::Error(error.to_string()),
+            duration: Duration::ZERO,
+            attempts: 0,
+            allow_failure: false,
+        }
+    }
+}
+
+/// What happened to the commands of a job
+pub struct JobReport {
+    pub index: usize,
+    pub name: String,
+    pub steps: Vec<StepReport>,
+    /// Reason the job was not run at all
+    pub skipped: Option<String>,
+}
+
+impl JobReport {
+    pub fn new(index: usize, name: &str) -> Self {
+        Self {
+            index,
+            name: name.to_string(),
+            steps: Vec::new(),
+            skipped: None,
+        }
+    }
+
+    pub fn skip(mut self, reason: &str) -> Self {
+        self.skipped = Some(reason.to_string());
+        self
+    }
+
+    /// Report for the job that stopped with the error
+    pub fn error(mut self, error: &Error) -> Self {
+        self.steps.push(StepReport::error("-", &error.to_string()));
+        self
+    }
+
+    /// Whether all the commands were up to date, so nothing was run
+    pub fn up_to_date(&self) -> bool {
+        !self.steps.is_empty()
+            && self
+                .steps
+                .iter()
+                .all(|s| matches!(s.status, StepStatus::UpToDate))
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.steps.iter().map(|s| s.duration).sum()
+    }
+
+    /// The step that made the job fail, if any
+    pub fn failure(&self) -> Option<&StepReport> {
+        self.steps
+            .iter()
+            .find(|s| !(s.status.success() || s.allow_failure))
+    }
+}
+
+/// How the outputs of a job are shown in the terminal
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputMode {
+    /// Commands write directly to the terminal
+    Inherit,
+    /// Outputs are collected and printed once the job is done
+    Buffer,
+    /// Output lines are printed as they come, prefixed with the job name
+    Prefix,
+}
+
+#[derive(Clone, Copy)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Output sink of a single job, it can be shared between the threads
+/// reading the stdout and stderr of the running command.
+#[derive(Clone)]
+pub struct JobOutput {
+    mode: OutputMode,
+    prefix: String,
+    buffer: Arc<Mutex<Vec<(Stream, String)>>>,
+    /// Partial lines of the stdout and the stderr, not printed yet
+    pending: Arc<Mutex<[String; 2]>>,
+    log: Arc<Mutex<Option<BufWriter<File>>>>,
+}
+
+impl JobOutput {
+    pub fn new(mode: OutputMode, name: &str) -> Self {
+        Self {
+            mode,
+            prefix: format!("[{}] ", name.trim().trim_end_matches(';')),
+            buffer: Arc::new(Mutex::new(Vec::new())),
+            pending: Arc::new(Mutex::new([String::new(), String::new()])),
+            log: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Also write the outputs of the commands to this file
+    pub fn log_to(&self, file: File) {
+        *self.log.lock().unwrap() = Some(BufWriter::new(file));
+    }
+
+    /// Write to the log file only, does nothing if there is no log
+    pub fn log(&self, text: &str) {
+        if let Some(writer) = self.log.lock().unwrap().as_mut() {
+            // failing to write the log shouldn't stop the job
+            writer.write_all(text.as_bytes()).ok();
+        }
+    }
+
+    pub fn mode(&self) -> OutputMode {
+        self.mode
+    }
+
+    fn has_log(&self) -> bool {
+        self.log.lock().unwrap().is_some()
+    }
+
+    /// Equivalent of `print!` for the job
+    pub fn out(&self, text: &str) {
+        self.write(Stream::Stdout, text);
+    }
+
+    /// Equivalent of `eprint!` for the job
+    pub fn err(&self, text: &str) {
+        self.write(Stream::Stderr, text);
+    }
+
+    fn write(&self, stream: Stream, text: &str) {
+        match self.mode {
+            OutputMode::Inherit => print_stream(stream, text),
+            OutputMode::Buffer => self.buffer.lock().unwrap().push((stream, text.to_string())),
+            OutputMode::Prefix => {
+                let mut pending = self.pending.lock().unwrap();
+                let pending = &mut pending[stream as usize];
+                pending.push_str(text);
+                while let Some((line, rest)) = pending.split_once('\n') {
+                    print_stream(stream, &format!("{}{}\n", self.prefix, line));
+                    *pending = rest.to_string();
+                }
+            }
+        }
+    }
+
+    /// Print everything collected till now, only useful for [`OutputMode::Buffer`]
+    pub fn flush(&self) {
+        let mut buffer = self.buffer.lock().unwrap();
+        for (stream, text) in buffer.iter() {
+            print_stream(*stream, text);
+        }
+        buffer.clear();
+    }
+
+    /// Run the process, and send its outputs to this job's output,
+    /// the stdout is also saved in `capture` if given
+    ///
+    /// With a timeout, the process is started in its own process
+    /// group, and the whole group is killed once the time is up.
+    pub fn run(
+        &self,
+        argv: &[String],
+        env: &[(String, String)],
+        timeout: Option<Duration>,
+        capture: Option<&mut String>,
+    ) -> Result<StepStatus, Error> {
+        let redirect = if self.mode == OutputMode::Inherit && !self.has_log() && capture.is_none() {
+            Redirection::None
+        } else {
+            Redirection::Pipe
+        };
+        #[allow(unused_mut)]
+        let mut config = PopenConfig {
+            stdout: redirect.try_clone()?,
+            stderr: redirect,
+            env: if env.is_empty() {
+                None
+            } else {
+                let mut vars = PopenConfig::current_env();
+                vars.extend(env.iter().map(|(k, v)| (k.into(), v.into())));
+                Some(vars)
+            },
+            ..Default::default()
+        };
+        #[cfg(unix)]
+        {
+            config.setpgid = timeout.is_some();
+        }
+        let mut proc = Popen::create(argv, config)?;
+        let _group = timeout.and(proc.pid()).map(ProcessGroup::new);
+        let readers = [
+            (Stream::Stdout, proc.stdout.take()),
+            (Stream::Stderr, proc.stderr.take()),
+        ]
+        .into_iter()
+        .filter_map(|(stream, file)| {
+            file.map(|f| {
+                let keep = capture.is_some() && matches!(stream, Stream::Stdout);
+                self.forward(stream, f, keep)
+            })
+        })
+        .collect::<Vec<_>>();
+        let status = match timeout {
+            Some(t) => match proc.wait_timeout(t)? {
+                Some(status) => StepStatus::from_exit(status),
+                None => {
+                    kill_group(&mut proc)?;
+                    proc.wait()?;
+                    StepStatus::TimedOut(t)
+                }
+            },
+            None => StepStatus::from_exit(proc.wait()?),
+        };
+        let captured: String = readers
+            .into_iter()
+            .map(|r| r.join().expect("Output reader thread panicked"))
+            .collect();
+        if let Some(capture) = capture {
+            *capture = captured;
+        }
+        Ok(status)
+    }
+
+    /// Run the commands connected with pipes, the stdout of each one
+    /// going to the stdin of the next, like `cmd1 | cmd2 | cmd3`
+    ///
+    /// Each command is given with its own environment variables, and
+    /// the status of each of them is returned in the same order. With
+    /// a timeout, all of them are started in the process group of the
+    /// first one, and the whole group is killed once the time is up.
+    pub fn run_piped(
+        &self,
+        commands: &[CommandArgs],
+        timeout: Option<Duration>,
+    ) -> Result<Vec<StepStatus>, Error> {
+        let piped = self.mode != OutputMode::Inherit || self.has_log();
+        let mut procs: Vec<Child> = Vec::new();
+        let mut readers = Vec::new();
+        for (i, (argv, env)) in commands.iter().enumerate() {
+            let mut cmd = process::Command::new(&argv[0]);
+            cmd.args(&argv[1..]).envs(env.iter().map(|(k, v)| (k, v)));
+            if let Some(stdout) = procs.last_mut().and_then(|p| p.stdout.take()) {
+                cmd.stdin(stdout);
+            }
+            if piped || i + 1 < commands.len() {
+                cmd.stdout(Stdio::piped());
+            }
+            if piped {
+                cmd.stderr(Stdio::piped());
+            }
+            #[cfg(unix)]
+            if timeout.is_some() {
+                use std::os::unix::process::CommandExt;
+                // 0 makes a new group for the first one
+                cmd.process_group(procs.first().map_or(0, |p| p.id() as i32));
+            }
+            match cmd.spawn() {
+                Ok(mut proc) => {
+                    if let Some(f) = proc.stderr.take() {
+                        readers.push(self.forward(Stream::Stderr, f, false));
+                    }
+                    procs.push(proc);
+                }
+                Err(e) => {
+                    for proc in &mut procs {
+                        proc.kill().ok();
+                        proc.wait().ok();
+                    }
+                    return Err(Error::new(e).context(format!("Couldn't run {:?}", argv[0])));
+                }
+            }
+        }
+        if let Some(f) = procs.last_mut().and_then(|p| p.stdout.take()) {
+            readers.push(self.forward(Stream::Stdout, f, false));
+        }
+        let _group = timeout
+            .and(procs.first().map(|p| p.id()))
+            .map(ProcessGroup::new);
+        let start = Instant::now();
+        let mut statuses = Vec::new();
+        for i in 0..procs.len() {
+            let status = match timeout {
+                Some(t) => match wait_timeout(&mut procs[i], t.saturating_sub(start.elapsed()))? {
+                    Some(status) => StepStatus::from_exit(status),
+                    None => {
+                        kill_group(&mut procs[0])?;
+                        for proc in &mut procs[i..] {
+                            proc.wait()?;
+                        }
+                        statuses.resize(procs.len(), StepStatus::TimedOut(t));
+                        break;
+                    }
+                },
+                None => StepStatus::from_exit(exit_status(procs[i].wait()?)),
+            };
+            statuses.push(status);
+        }
+        for r in readers {
+            r.join().expect("Output reader thread panicked");
+        }
+        Ok(statuses)
+    }
+
+    /// Forward the output to the job's output and log, the thread
+    /// returns the output if `keep` is true
+    fn forward<R: Read + Send + 'static>(
+        &self,
+        stream: Stream,
+        reader: R,
+        keep: bool,
+    ) -> thread::JoinHandle<String> {
+        let output = self.clone();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(reader);
+            let mut line: Vec<u8> = Vec::new();
+            let mut kept = String::new();
+            while let Ok(n) = reader.read_until(b'\n', &mut line) {
+                if n == 0 {
+                    break;
+                }
+                if !line.ends_with(b"\n") {
+                    line.push(b'\n');
+                }
+                let text = String::from_utf8_lossy(&line);
+                output.write(stream, &text);
+                match stream {
+                    Stream::Stdout => output.log(&text),
+                    // stderr lines are marked to tell them apart in the log
+                    Stream::Stderr => output.log(&format!("{}{}", STDERR_LOG_PREFIX, text)),
+                }
+                if keep {
+                    kept.push_str(&text);
+                }
+                line.clear();
+            }
+            kept
+        })
+    }
+}
+
+/// Prefix for the lines of stderr in the log files
+pub const STDERR_LOG_PREFIX: &str = "[stderr] ";
+
+/// Process that can be killed along with the ones it started
+trait Process {
+    fn pid(&self) -> Option<u32>;
+    fn kill(&mut self) -> std::io::Result<()>;
+}
+
+impl Process for Popen {
+    fn pid(&self) -> Option<u32> {
+        Popen::pid(self)
+    }
+
+    fn kill(&mut self) -> std::io::Result<()> {
+        Popen::kill(self)
+    }
+}
+
+impl Process for Child {
+    fn pid(&self) -> Option<u32> {
+        Some(self.id())
+    }
+
+    fn kill(&mut self) -> std::io::Result<()> {
+        Child::kill(self)
+    }
+}
+
+/// Kill the process, and on unix the whole process group it leads
+fn kill_group(proc: &mut impl Process) -> Result<(), Error> {
+    #[cfg(unix)]
+    if let Some(pid) = proc.pid() {
+        // negative pid sends the signal to the whole process group
+        if unsafe { libc::kill(-(pid as i32), libc::SIGKILL) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        return Ok(());
+    }
+    Ok(proc.kill()?)
+}
+
+/// Wait for the process to finish, [`None`] if it is still running
+/// after the timeout
+fn wait_timeout(proc: &mut Child, timeout: Duration) -> Result<Option<ExitStatus>, Error> {
+    let end = Instant::now() + timeout;
+    loop {
+        if let Some(status) = proc.try_wait()? {
+            return Ok(Some(exit_status(status)));
+        }
+        if Instant::now() >= end {
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Exit status of the [`std::process`] in the form used in the reports
+fn exit_status(status: process::ExitStatus) -> ExitStatus {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(sig) = status.signal() {
+            return ExitStatus::Signaled(sig as u8);
+        }
+    }
+    match status.code() {
+        Some(code) => ExitStatus::Exited(code as u32),
+        None => ExitStatus::Undetermined,
+    }
+}
+
+/// Process group of a command running outside the terminal's
+/// foreground group, which doesn't get the Ctrl-C from the terminal
+///
+/// The groups are killed when anek is interrupted (SIGINT/SIGTERM),
+/// so they don't keep running after it exits.
+struct ProcessGroup(u32);
+
+#[cfg(unix)]
+mod interrupt {
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use std::sync::{Mutex, Once};
+    use std::thread;
+
+    pub static GROUPS: Mutex<Option<HashSet<u32>>> = Mutex::new(None);
+    static PIPE_WRITE: AtomicI32 = AtomicI32::new(-1);
+    static INSTALL: Once = Once::new();
+
+    extern "C" fn on_signal(sig: libc::c_int) {
+        // only async-signal-safe calls here, the thread below does the rest
+        let byte = sig as u8;
+        unsafe {
+            libc::write(
+                PIPE_WRITE.load(Ordering::SeqCst),
+                &byte as *const u8 as *const libc::c_void,
+                1,
+            );
+        }
+    }
+
+    /// Install the signal handlers, only once
+    pub fn install() {
+        INSTALL.call_once(|| {
+            let mut fds = [0; 2];
+            if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+                return;
+            }
+            PIPE_WRITE.store(fds[1], Ordering::SeqCst);
+            thread::spawn(move || {
+                let mut byte = 0u8;
+                let n = unsafe { libc::read(fds[0], &mut byte as *mut u8 as *mut libc::c_void, 1) };
+                if n != 1 {
+                    return;
+                }
+                if let Some(groups) = GROUPS.lock().unwrap().as_ref() {
+                    for pid in groups {
+                        unsafe { libc::kill(-(*pid as i32), libc::SIGKILL) };
+                    }
+                }
+                std::process::exit(128 + byte as i32);
+            });
+            let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            unsafe {
+                libc::signal(libc::SIGINT, handler);
+                libc::signal(libc::SIGTERM, handler);
+            }
+        });
+    }
+}
+
+impl ProcessGroup {
+    fn new(pid: u32) -> Self {
+        #[cfg(unix)]
+        {
+            interrupt::install();
+            interrupt::GROUPS
+                .lock()
+                .unwrap()
+                .get_or_insert_with(Default::default)
+                .insert(pid);
+        }
+        Self(pid)
+    }
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(groups) = interrupt::GROUPS.lock().unwrap().as_mut() {
+            groups.remove(&self.0);
+        }
+    }
+}
+
+/// Parse durations like `90`, `1.5s`, `500ms`, `10m` or `2h`, seconds by default
+pub fn parse_duration(dur: &str) -> Result<Duration, Error> {
+    let dur = dur.trim();
+    let split = dur
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(dur.len());
+    let (num, unit) = dur.split_at(split);
+    let num: f64 = num
+        .parse()
+        .map_err(|_| Error::msg(format!("Invalid duration: {:?}", dur)))?;
+    let secs = match unit.trim() {
+        "ms" => num / 1000.0,
+        "" | "s" => num,
+        "m" => num * 60.0,
+        "h" => num * 3600.0,
+        u => return Err(Error::msg(format!("Invalid duration unit: {:?}", u))),
+    };
+    Duration::try_from_secs_f64(secs)
+        .map_err(|e| Error::msg(format!("Invalid duration {:?}: {}", dur, e)))
+}
+
+fn print_stream(stream: Stream, text: &str) {
+    match stream {
+        Stream::Stdout => print!("{}", text),
+        Stream::Stderr => eprint!("{}", text),
+    }
+}
+
+/// Logs of a single run, saved in `.anek/logs/<run-id>/`
+///
+/// Each job gets its own log file with the rendered commands, their
+/// outputs and exit status, and the `index` file lists all the jobs
+/// of the run with their status and log file.
+pub struct RunLog {
+    dir: PathBuf,
+}
+
+impl RunLog {
+    pub fn create(logs_dir: &Path) -> Result<Self, Error> {
+        let run_id = Local::now().format("%Y-%m-%d_%H-%M-%S").to_string();
+        let mut dir = logs_dir.join(&run_id);
+        let mut i = 1;
+        while dir.exists() {
+            i += 1;
+            dir = logs_dir.join(format!("{}_{}", run_id, i));
+        }
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn job_file(&self, index: usize, name: &str) -> PathBuf {
+        let mut filename = format!("{:04}_", index);
+        for c in name.chars() {
+            if c.is_alphanumeric() || "-.,".contains(c) {
+                filename.push(c);
+            } else if !filename.ends_with('_') {
+                filename.push('_');
+            }
+        }
+        let filename: String = filename.trim_end_matches('_').chars().take(120).collect();
+        self.dir.join(format!("{}.log", filename))
+    }
+
+    pub fn write_index(&self, reports: &[JobReport]) -> Result<(), Error> {
+        let mut writer = BufWriter::new(File::create(self.dir.join("index"))?);
+        writeln!(
+            writer,
+            "# {}",
+            std::env::args().collect::<Vec<String>>().join(" ")
+        )?;
+        for report in reports {
+            let status = if let Some(reason) = &report.skipped {
+                format!("skipped ({})", reason)
+            } else if let Some(step) = report.failure() {
+                format!("{} ({})", step.status, step.command)
+            } else if report.up_to_date() {
+                StepStatus::UpToDate.to_string()
+            } else {
+                StepStatus::Success.to_string()
+            };
+            writeln!(
+                writer,
+                "{}\t{}\t{:?}\t{}\t{}",
+                report.index,
+                status,
+                report.duration(),
+                self.job_file(report.index, &report.name)
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy(),
+                report.name
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Run the jobs using the given number of worker threads
+///
+/// Jobs are started in order and their outputs are printed in the
+/// same order. No new jobs are started after one of them fails
+/// (unless `keep_going`), or returns an error. The reports of the
+/// finished jobs are returned, or the first error once the running
+/// ones are finished.
+pub fn run_parallel<T, N, F>(
+    workers: usize,
+    keep_going: bool,
+    jobs: &[T],
+    mode: OutputMode,
+    name: N,
+    job: F,
+) -> Result<Vec<JobReport>, Error>
+where
+    T: Sync,
+    N: Fn(&T) -> String + Sync,
+    F: Fn(usize, &T, &JobOutput) -> Result<JobReport, Error> + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let (tx, rx) = mpsc::channel::<(usize, JobOutput, Result<JobReport, Error>)>();
+    thread::scope(|s| {
+        for _ in 0..workers.clamp(1, jobs.len().max(1)) {
+            let tx = tx.clone();
+            let (next, stop, name, job) = (&next, &stop, &name, &job);
+            s.spawn(move || loop {
+                if stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                if i >= jobs.len() {
+                    break;
+                }
+                let output = JobOutput::new(mode, &name(&jobs[i]));
+                let res = job(i, &jobs[i], &output);
+                let failed = match &res {
+                    Ok(report) => report.failure().is_some(),
+                    Err(_) => true,
+                };
+                if failed && (!keep_going || res.is_err()) {
+                    stop.store(true, Ordering::SeqCst);
+                }
+                if tx.send((i, output, res)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(tx);
+
+        let mut finished = BTreeMap::new();
+        let mut printed = 0;
+        let mut reports = Vec::new();
+        let mut error = None;
+        for (i, output, res) in rx {
+            finished.insert(i, (output, res));
+            while let Some((output, res)) = finished.remove(&printed) {
+                output.flush();
+                match res {
+                    Ok(report) => reports.push(report),
+                    Err(e) => {
+                        error.get_or_insert(e);
+                    }
+                }
+                printed += 1;
+            }
+        }
+        match error {
+            Some(e) => Err(e),
+            None => Ok(reports),
+        }
+    })
+}
+
+/// Print the table of failed jobs, and error if there are any
+pub fn summarize(reports: &[JobReport], total: usize) -> Result<(), Error> {
+    let steps = |f: fn(&StepReport) -> bool| -> Vec<(&JobReport, &StepReport)> {
+        reports
+            .iter()
+            .flat_map(|r| r.steps.iter().filter(|s| f(s)).map(move |s| (r, s)))
+            .collect()
+    };
+    let allowed = steps(|s| s.allow_failure && !s.status.success());
+    if !allowed.is_empty() {
+        print_steps(&"Allowed Failures".yellow().bold(), &allowed);
+    }
+    let skipped = steps(|s| matches!(s.status, StepStatus::Skipped(_)));
+    if !skipped.is_empty() {
+        print_steps(&"Skipped Steps".yellow().bold(), &skipped);
+    }
+    let failed: Vec<(&JobReport, &StepReport)> = reports
+        .iter()
+        .filter_map(|r| r.failure().map(|s| (r, s)))
+        .collect();
+    if failed.is_empty() {
+        return Ok(());
+    }
+    print_steps(&"Failed Jobs".bright_red().bold(), &failed);
+    if reports.len() < total {
+        eprintln!(
+            "{}: {} jobs were not run",
+            "Skipped".yellow(),
+            total - reports.len()
+        );
+    }
+    // the exit code of the command when only one of them failed
+    let exit_code = match failed.as_slice() {
+        [(_, step)] => match step.status {
+            StepStatus::Failed(ExitStatus::Exited(c)) => c as i32,
+            StepStatus::Failed(ExitStatus::Signaled(s)) => 128 + s as i32,
+            _ => 1,
+        },
+        _ => 1,
+    };
+    Err(JobsFailed {
+        failed: failed.len(),
+        total,
+        exit_code,
+    }
+    .into())
+}
+
+/// Error for the run with failed jobs, with the exit code for anek
+#[derive(Debug)]
+pub struct JobsFailed {
+    pub failed: usize,
+    pub total: usize,
+    pub exit_code: i32,
+}
+
+impl std::fmt::Display for JobsFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} of {} jobs failed", self.failed, self.total)
+    }
+}
+
+impl std::error::Error for JobsFailed {}
+
+fn print_steps(title: &ColoredString, steps: &[(&JobReport, &StepReport)]) {
+    eprintln!("{}", title);
+    eprintln!(
+        "{:>6}  {:24}  {:>5}  {:16}  Input",
+        "Job", "Status", "Tries", "Command"
+    );
+    for (job, step) in steps {
+        eprintln!(
+            "{:>6}  {:24}  {:>5}  {:16}  {}",
+            job.index,
+            step.status.to_string(),
+            step.attempts,
+            step.command,
+            job.name
+        );
+    }
+}
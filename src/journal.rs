@@ -0,0 +1,118 @@
+use anyhow::Error;
+use chrono::Local;
+use itertools::Itertools;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::dtypes::PipelineStep;
+use crate::variable;
+
+/// Journal of the jobs run in an anek directory
+///
+/// Each finished job appends a line `key<TAB>status<TAB>time<TAB>name`
+/// to `.anek/logs/journal`, the key identifies the job by its
+/// commands and the values of the variables they use. The last line
+/// for a key is the one that counts. The file (and the logs
+/// directory) is only made when the first job is recorded.
+pub struct Journal {
+    path: PathBuf,
+    status: HashMap<String, bool>,
+    file: Mutex<Option<File>>,
+}
+
+impl Journal {
+    pub fn open(logs_dir: &Path) -> Result<Self, Error> {
+        let path = logs_dir.join("journal");
+        let mut status = HashMap::new();
+        if path.exists() {
+            for (i, line) in variable::input_lines(&path, None)? {
+                let mut parts = line.split('\t');
+                match (parts.next(), parts.next()) {
+                    (Some(key), Some(st)) => {
+                        status.insert(key.to_string(), st == "success");
+                    }
+                    _ => eprintln!("Invalid journal line# {}: {:?}", i, line),
+                }
+            }
+        }
+        Ok(Self {
+            path,
+            status,
+            file: Mutex::new(None),
+        })
+    }
+
+    /// Key for the job made from the commands (their templates), the
+    /// steps selected to run, and the values of the variables used in them
+    ///
+    /// The rendered commands are not used as rendering can run the
+    /// `$(...)` commands in the templates, which shouldn't happen
+    /// for the jobs that are skipped.
+    pub fn job_key(
+        name: &str,
+        commands: &[PipelineStep],
+        selected: &HashSet<usize>,
+        variables: &HashMap<String, String>,
+        used: &HashSet<&str>,
+    ) -> String {
+        let mut key = name.to_string();
+        // a run of some of the steps is a different job than the whole one
+        if selected.len() < commands.len() {
+            key.push_str(&format!(" steps={}", selected.iter().sorted().join(",")));
+        }
+        for step in commands {
+            key.push('\n');
+            key.push_str(step.command.template().original());
+            for (var, val) in step.overwrite.iter().sorted() {
+                key.push_str(&format!(" {}={}", var, val));
+            }
+        }
+        for var in used.iter().sorted() {
+            key.push_str(&format!(
+                "\n{}={}",
+                var,
+                variables.get(*var).map_or("", |v| v)
+            ));
+        }
+        format!("{:016x}", fnv_hash(&key))
+    }
+
+    /// Whether the job succeeded last time, [`None`] if it was never run
+    pub fn succeeded(&self, key: &str) -> Option<bool> {
+        self.status.get(key).copied()
+    }
+
+    pub fn record(&self, key: &str, success: bool, name: &str) -> Result<(), Error> {
+        let mut file = self.file.lock().unwrap();
+        if file.is_none() {
+            if let Some(dir) = self.path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            *file = Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?,
+            );
+        }
+        writeln!(
+            file.as_mut().unwrap(),
+            "{}\t{}\t{}\t{}",
+            key,
+            if success { "success" } else { "failed" },
+            Local::now().format("%Y-%m-%d %H:%M:%S"),
+            name
+        )?;
+        Ok(())
+    }
+}
+
+/// FNV-1a hash, so the keys stay the same between different builds
+fn fnv_hash(s: &str) -> u64 {
+    s.bytes().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
@@ -56,7 +56,7 @@ pub struct CliArgs {
     #[arg(short, long, action)]
     all: bool,
     #[arg(default_value = ".", value_hint = ValueHint::DirPath)]
-    path: PathBuf,
+    pub path: PathBuf,
 }
 
 pub fn list_options(args: CliArgs) -> Result<(), Error> {
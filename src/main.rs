@@ -2,6 +2,8 @@ use anyhow::Error;
 use chrono::Local;
 use clap::{CommandFactory, Parser, Subcommand};
 use colored::Colorize;
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::time::Instant;
 
 mod completions;
@@ -9,6 +11,8 @@ mod dtypes;
 mod edit;
 mod export;
 mod graph;
+mod jobs;
+mod journal;
 mod list;
 mod new;
 mod render;
@@ -28,6 +32,36 @@ struct Cli {
     /// outputs.
     #[arg(short, long)]
     quiet: bool,
+    /// Don't run the shell commands in the templates
+    ///
+    /// The shell commands (`$(...)`) in the templates and the
+    /// overwrite values are not run in safe mode, and rendering a
+    /// template that has them is an error. Safe mode is the default
+    /// for `export`, `render` and `report`, and for all the
+    /// subcommands if `safe=true` is in `.anek/config`.
+    #[arg(long, conflicts_with = "unsafe_mode")]
+    safe: bool,
+    /// Run the shell commands in the templates, even in safe mode
+    #[arg(long = "unsafe")]
+    unsafe_mode: bool,
+    /// Error if a variable is defined twice in the same input
+    ///
+    /// Variables defined more than once in an input (e.g. in the
+    /// files of its directory) are a warning by default, the last
+    /// one read is used. Overriding the variables from the included
+    /// inputs is fine. Also enabled by `strict=true` in `.anek/config`.
+    #[arg(long)]
+    strict: bool,
+    /// Which files override the others inside the input directories
+    ///
+    /// When a directory of inputs (or the `.d` directory of an input)
+    /// defines a variable more than once, `inner` makes the files
+    /// inside the subdirectories override the ones outside, and
+    /// `outer` the other way around. By default the files are read in
+    /// the order they're listed. Also set by `input_precedence=inner`
+    /// (or `outer`) in `.anek/config`.
+    #[arg(long, value_name = "inner|outer", value_parser = variable::Precedence::parse)]
+    input_precedence: Option<variable::Precedence>,
     /// Command to run
     ///
     /// Any command that you want to run, all the args after this will
@@ -102,36 +136,109 @@ enum Action {
     Graph(graph::CliArgs),
 }
 
+impl Action {
+    /// Path the subcommand looks for the `.anek` directory from
+    fn path(&self) -> PathBuf {
+        match self {
+            Action::Variable(a) => a.path.clone(),
+            Action::List(a) => a.path.clone(),
+            Action::Edit(a) => a.path.clone(),
+            Action::Report(a) => a.path.clone(),
+            Action::View(a) => a.path.clone(),
+            Action::Show(a) => a.path.clone(),
+            Action::Graph(a) => a.path.clone(),
+            _ => PathBuf::from("."),
+        }
+    }
+}
+
+/// Config of the anek directory the subcommand uses, empty if there
+/// is none
+fn action_config(action: &Action) -> HashMap<String, String> {
+    dtypes::AnekDirectory::from(&action.path())
+        .and_then(|d| d.config())
+        .unwrap_or_default()
+}
+
+fn set_safe_mode(args: &Cli) -> Result<(), Error> {
+    let safe = if args.unsafe_mode {
+        false
+    } else if args.safe {
+        true
+    } else {
+        match action_config(&args.action).get("safe") {
+            Some(s) => s
+                .parse()
+                .map_err(|_| Error::msg(format!("Invalid safe value in config: {:?}", s)))?,
+            None => matches!(
+                args.action,
+                Action::Export(_) | Action::Render(_) | Action::Report(_)
+            ),
+        }
+    };
+    dtypes::set_safe_mode(safe);
+    Ok(())
+}
+
+/// How the input files are merged, from the flags and the config
+fn input_options(args: &Cli) -> Result<variable::InputOptions, Error> {
+    let config = action_config(&args.action);
+    let strict = match config.get("strict") {
+        Some(s) if !args.strict => s
+            .parse()
+            .map_err(|_| Error::msg(format!("Invalid strict value in config: {:?}", s)))?,
+        _ => args.strict,
+    };
+    let precedence = match (args.input_precedence, config.get("input_precedence")) {
+        (Some(p), _) => Some(p),
+        (None, Some(p)) => Some(variable::Precedence::parse(p)?),
+        (None, None) => None,
+    };
+    Ok(variable::InputOptions::new(precedence, strict))
+}
+
 fn main() {
     let g_args = Cli::parse();
 
     let start_time = Local::now().format("%Y-%m-%d %H:%M:%S");
     let start = Instant::now();
+    let input_opts = match set_safe_mode(&g_args).and_then(|_| input_options(&g_args)) {
+        Ok(opts) => opts,
+        Err(e) => {
+            eprintln!("{}: {}", "Error".bright_red(), e);
+            std::process::exit(1);
+        }
+    };
     let action_result: Result<(), Error> = match g_args.action {
         Action::New(args) => new::new_config(args),
-        Action::Variable(args) => variable::run_command(args),
+        Action::Variable(args) => variable::run_command(args, &input_opts),
         Action::List(args) => list::list_options(args),
         Action::Edit(args) => edit::edit_file(args),
-        Action::Export(args) => export::run_command(args),
-        Action::Run(args) => run::run_command(args),
-        Action::Render(args) => render::run_command(args),
+        Action::Export(args) => export::run_command(args, &input_opts),
+        Action::Run(args) => run::run_command(args, &input_opts),
+        Action::Render(args) => render::run_command(args, &input_opts),
         Action::Completions(args) => {
             let mut clap_app = Cli::command();
             completions::print_completions(args, &mut clap_app)
         }
         Action::Report(args) => report::save_report(args),
         Action::View(args) => view::cmd(args),
-        Action::Show(args) => show::show_file(args),
-        Action::Graph(args) => graph::print_dot(args),
+        Action::Show(args) => show::show_file(args, &input_opts),
+        Action::Graph(args) => graph::print_dot(args, &input_opts),
     };
     let duration = start.elapsed();
 
-    if g_args.quiet {
-        return;
+    if !g_args.quiet {
+        if let Err(e) = &action_result {
+            eprintln!("{}: {}", "Error".bright_red(), e);
+        }
+        eprintln!("{:12}: {}", "Started at".bright_blue().bold(), start_time);
+        eprintln!("{:12}: {:?}", "Time Elapsed".bright_blue().bold(), duration);
     }
     if let Err(e) = action_result {
-        eprintln!("{}: {}", "Error".bright_red(), e);
+        let code = e
+            .downcast_ref::<jobs::JobsFailed>()
+            .map_or(1, |f| f.exit_code);
+        std::process::exit(code);
     }
-    eprintln!("{:12}: {}", "Started at".bright_blue().bold(), start_time);
-    eprintln!("{:12}: {:?}", "Time Elapsed".bright_blue().bold(), duration);
 }
@@ -5,10 +5,11 @@ use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 use std::path::PathBuf;
-use string_template_plus::{Render, RenderOptions, Template};
+use string_template_plus::Template;
 
-use crate::dtypes::AnekDirectory;
+use crate::dtypes::{self, AnekDirectory};
 use crate::run_utils::{self, variables_from_input};
+use crate::variable::InputOptions;
 
 #[derive(Args)]
 pub struct CliArgs {
@@ -135,6 +136,7 @@ impl RenderFileContents {
         &self,
         inputs: Vec<HashMap<String, String>>,
         overwrite: &HashMap<String, String>,
+        input_opts: &InputOptions,
     ) -> Result<(), Error> {
         for part in &self.contents {
             match part {
@@ -157,25 +159,21 @@ impl RenderFileContents {
                 RenderFileContentsType::Snippet(templ, batch) => {
                     if let Some(batch) = batch {
                         let ad = AnekDirectory::from(&PathBuf::default())?;
-                        let inputs =
-                            run_utils::input_files(&ad, &vec![batch.to_string()], &HashSet::new())?;
+                        let inputs = run_utils::input_files(
+                            &ad,
+                            &vec![batch.to_string()],
+                            &HashSet::new(),
+                            input_opts,
+                        )?;
                         for inp in &inputs {
                             let input = variables_from_input(&inp, &overwrite)?;
-                            let renderops = RenderOptions {
-                                variables: input,
-                                wd: PathBuf::default(),
-                                shell_commands: true,
-                            };
-                            print!("{}", templ.render(&renderops)?);
+                            let renderops = dtypes::render_options(input);
+                            print!("{}", dtypes::render_template(templ, &renderops)?);
                         }
                     } else {
                         for input in &inputs {
-                            let renderops = RenderOptions {
-                                variables: input.clone(),
-                                wd: PathBuf::default(),
-                                shell_commands: true,
-                            };
-                            print!("{}", templ.render(&renderops)?);
+                            let renderops = dtypes::render_options(input.clone());
+                            print!("{}", dtypes::render_template(templ, &renderops)?);
                         }
                     }
                 }
@@ -185,7 +183,7 @@ impl RenderFileContents {
     }
 }
 
-pub fn run_command(args: CliArgs) -> Result<(), Error> {
+pub fn run_command(args: CliArgs, input_opts: &InputOptions) -> Result<(), Error> {
     let anek_dir = AnekDirectory::from_pwd()?;
     let template = if args.template {
         RenderFileContents::snippet(&args.file, None)?
@@ -196,11 +194,11 @@ pub fn run_command(args: CliArgs) -> Result<(), Error> {
     let cmd_args = run_utils::command_args(&args.inputs);
     let overwrite: HashMap<String, String> = run_utils::overwrite_vars(&args.inputs, &cmd_args)?;
 
-    let input_files = run_utils::inputs(&anek_dir, &args.inputs, &HashSet::new())?;
+    let input_files = run_utils::inputs(&anek_dir, &args.inputs, &HashSet::new(), input_opts)?;
     let variables = input_files
         .iter()
         .map(|inp| -> Result<_, Error> { run_utils::variables_from_input(inp, &overwrite) })
         .collect::<Result<Vec<_>, Error>>()?;
-    template.print_render(variables, &overwrite)?;
+    template.print_render(variables, &overwrite, input_opts)?;
     Ok(())
 }
@@ -15,7 +15,7 @@ pub struct CliArgs {
     filename: PathBuf,
     /// Anek Directory Path
     #[arg(default_value = ".", value_hint=ValueHint::DirPath)]
-    path: PathBuf,
+    pub path: PathBuf,
 }
 
 fn capitalize(s: &str) -> String {
@@ -1,9 +1,21 @@
-use anyhow::Error;
+use anyhow::{Context, Error};
 use clap::{Args, ValueHint};
+use colored::Colorize;
+use itertools::Itertools;
 use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use crate::dtypes::{AnekDirectory, Command};
+use crate::dtypes::{
+    AnekDirectory, Capture, Command, CommandInputs, Pipeline, PipelineStep, RunOptions, Shell,
+};
+use crate::jobs::{self, JobOutput, JobReport, OutputMode, RunLog, StepReport, StepStatus};
+use crate::journal::Journal;
 use crate::run_utils;
+use crate::variable::InputOptions;
 
 #[derive(Args)]
 pub struct CliArgs {
@@ -28,6 +40,133 @@ pub struct CliArgs {
     /// sure it's what you want to run before running it.
     #[arg(short, long)]
     demo: bool,
+    /// Number of jobs to run in parallel
+    ///
+    /// Each input (e.g. from a batch or a loop) with the whole
+    /// command or pipeline is a job. Jobs are run in this many
+    /// workers, while the commands of a pipeline still run one after
+    /// another inside a job. The outputs of each job are collected
+    /// and printed in order once the job is finished, unless
+    /// `--stream` is used.
+    #[arg(short, long, default_value = "1", value_name = "N")]
+    jobs: usize,
+    /// Stream the outputs of the jobs as they run
+    ///
+    /// Each line of the output is prefixed with the name of the job
+    /// it belongs to, so you can tell the parallel jobs apart.
+    #[arg(long)]
+    stream: bool,
+    /// Keep running other jobs when one of them fails
+    ///
+    /// A command exiting with non-zero status stops the rest of the
+    /// pipeline for that job. By default no new jobs are started
+    /// after that, with this flag the other jobs will still be
+    /// run. The failed jobs are listed at the end either way.
+    #[arg(short, long)]
+    keep_going: bool,
+    /// Save the logs of each job
+    ///
+    /// Saves the rendered commands, their outputs, exit status and
+    /// time taken for each job in a separate file inside
+    /// `.anek/logs/<run-id>/`, along with an `index` file listing all
+    /// the jobs in that run.
+    #[arg(short = 'L', long)]
+    log: bool,
+    /// Skip the jobs that succeeded in previous runs
+    ///
+    /// Each run keeps a journal of its jobs in `.anek/logs/journal`,
+    /// where jobs are identified by the command or pipeline, and the
+    /// values of the variables they use. With this flag, the jobs
+    /// that already succeeded with the same commands and variables
+    /// are skipped, so you can continue an interrupted run.
+    #[arg(short, long, conflicts_with = "rerun_failed")]
+    resume: bool,
+    /// Only run the jobs that failed in previous runs
+    ///
+    /// Same as `--resume`, but also skips the jobs that have never
+    /// been run before.
+    #[arg(long)]
+    rerun_failed: bool,
+    /// Run the commands even if their outputs are up to date
+    ///
+    /// Commands can declare the files they read and make with
+    /// `#@input=<template>` and `#@output=<template>` lines, the
+    /// command is not run for a job if all its outputs are newer
+    /// than its inputs. This flag runs them anyway.
+    #[arg(short, long)]
+    force: bool,
+    /// Kill the commands that run longer than this
+    ///
+    /// Duration can be in ms, s (default), m or h; e.g. `90`, `1.5m`.
+    /// The command and all the processes it started are killed, and
+    /// the job is marked as timed out. Commands can set their own
+    /// timeout with a `#@timeout=<duration>` line.
+    #[arg(long, value_parser = jobs::parse_duration, value_name = "DURATION")]
+    timeout: Option<Duration>,
+    /// Retry the failed commands this many times
+    ///
+    /// The wait time between the tries starts at 1 second and doubles
+    /// each time. Commands can set their own number of retries with a
+    /// `#@retry=<N>` line.
+    #[arg(long, default_value = "0", value_name = "N")]
+    retry: usize,
+    /// Export the variables as environment variables for the commands
+    ///
+    /// All the variables of the job are exported with the
+    /// `--env-prefix` added to their names. The job index, name and
+    /// the anek directory are also exported as `ANEK_JOB_INDEX`,
+    /// `ANEK_JOB_NAME` and `ANEK_ROOT`. Commands can turn this on or
+    /// off for themselves with a `#@env=true|false` line, and use a
+    /// different prefix with `#@env-prefix=<prefix>`.
+    #[arg(short, long)]
+    env: bool,
+    /// Prefix for the names of the exported variables
+    #[arg(long, default_value = "ANEK_", value_name = "PREFIX")]
+    env_prefix: String,
+    /// Shell to run the commands in
+    ///
+    /// Give the program with its arguments (e.g. `bash`, `zsh`,
+    /// `python -c`), the command is passed as the last argument, and
+    /// `-c` is used if only the program is given. Use `none` to split
+    /// the command into arguments and run it directly without a
+    /// shell. The default is `sh -c`, or the `shell` set in
+    /// `.anek/config`; commands can also use their own with a
+    /// `#@shell=<shell>` line, or with a shebang (`#!`) in the first
+    /// line to be run as a script.
+    #[arg(long, value_parser = Shell::parse)]
+    shell: Option<Shell>,
+    /// Quote the substituted values for the shell
+    ///
+    /// Each value from the variables, and the output of `$(...)`, is
+    /// quoted so the shell sees it as a single word, no matter the
+    /// spaces or special characters in it. Use the `raw` transformer
+    /// (e.g. `{flags:raw}`) for the values that are meant to be shell
+    /// fragments. Can also be turned on with `quote=true` in
+    /// `.anek/config`, or per command with a `#@quote=true|false` line.
+    #[arg(short = 'Q', long)]
+    quote: bool,
+    /// Connect the steps of the pipeline with pipes
+    ///
+    /// The stdout of each step goes to the stdin of the next one,
+    /// like `step1 | step2 | step3` in the shell. Same as having
+    /// `@pipe` in the pipeline file.
+    #[arg(long)]
+    pipe: bool,
+    /// Start the pipeline from this step
+    ///
+    /// Give the step's number (starting from 1) or the name of its
+    /// command, the steps before it are skipped.
+    #[arg(long, value_name = "STEP")]
+    from_step: Option<String>,
+    /// Stop the pipeline after this step
+    #[arg(long, value_name = "STEP")]
+    to_step: Option<String>,
+    /// Only run these steps of the pipeline
+    ///
+    /// Comma separated step numbers or command names, numbers can be
+    /// ranges like `2-4`, same as `--select-inputs`.
+    #[arg(long, value_name = "STEPS", conflicts_with_all = ["from_step", "to_step"])]
+    only_step: Option<String>,
     /// command to run (from .anek/commands/)
     ///
     /// The command file saved will have the command template inside
@@ -39,38 +178,592 @@ pub struct CliArgs {
     inputs: run_utils::Inputs,
 }
 
-pub fn run_command(args: CliArgs) -> Result<(), Error> {
+pub fn run_command(args: CliArgs, input_opts: &InputOptions) -> Result<(), Error> {
     let anek_dir = AnekDirectory::from_pwd()?;
-    let commands = if args.template {
-        vec![Command::new("-T-", &args.command)?]
+    let pipeline = if args.template {
+        Pipeline::single(Command::new("-T-", &args.command)?)
     } else if args.pipeline {
         run_utils::cmd_from_pipeline(&anek_dir, &args.command)?
     } else {
-        vec![anek_dir.command(&args.command)?]
+        Pipeline::single(anek_dir.command(&args.command)?)
     };
-
-    let mut variables: HashSet<&str> = HashSet::new();
-    for cmd in &commands {
-        cmd.template().parts().iter().for_each(|p| {
-            p.variables().iter().for_each(|v| {
-                variables.insert(v);
-            })
-        });
+    let pipe = args.pipe || pipeline.pipe;
+    let commands = pipeline.steps;
+    let dependencies = run_utils::step_dependencies(&commands)?;
+    if pipe && dependencies.is_some() {
+        return Err(Error::msg(
+            "Piped pipeline can't have dependencies between the steps",
+        ));
     }
 
+    let selected = run_utils::select_steps(
+        &commands,
+        args.from_step.as_deref(),
+        args.to_step.as_deref(),
+        args.only_step.as_deref(),
+    )?;
+
+    let variable_names: Vec<String> = commands.iter().flat_map(|c| c.variables()).collect();
+    let variables_used: HashSet<&str> = variable_names.iter().map(|v| v.as_str()).collect();
+
     let cmd_args = run_utils::command_args(&args.inputs);
     let overwrite: HashMap<String, String> = run_utils::overwrite_vars(&args.inputs, &cmd_args)?;
 
-    let input_files = run_utils::inputs(&anek_dir, &args.inputs, &variables)?;
+    let input_files = run_utils::inputs(&anek_dir, &args.inputs, &variables_used, input_opts)?;
     let total = input_files.len();
-    for (i, input) in input_files.iter().enumerate() {
+    if args.demo && !args.pipable {
+        eprintln!("{}: {}", "Jobs".bright_blue(), total);
+    }
+    let mode = if args.stream {
+        OutputMode::Prefix
+    } else if args.jobs > 1 {
+        OutputMode::Buffer
+    } else {
+        OutputMode::Inherit
+    };
+    let journal = Journal::open(&anek_dir.logs_dir())?;
+    let run_name = format!(
+        "{}:{}",
+        if args.template {
+            "template"
+        } else if args.pipeline {
+            "pipeline"
+        } else {
+            "command"
+        },
+        args.command
+    );
+    let config = anek_dir.config()?;
+    let shell = match (args.shell, config.get("shell")) {
+        (Some(shell), _) => shell,
+        (None, Some(shell)) => Shell::parse(shell)?,
+        (None, None) => Shell::default(),
+    };
+    let quote = match config.get("quote") {
+        Some(q) if !args.quote => q
+            .parse()
+            .context(format!("Invalid quote value in config: {:?}", q))?,
+        _ => args.quote,
+    };
+    let opts = RunOptions {
+        demo: args.demo,
+        pipable: args.pipable,
+        force: args.force,
+        timeout: args.timeout,
+        retry: args.retry,
+        env: args.env,
+        env_prefix: args.env_prefix.clone(),
+        shell,
+        quote,
+        root: anek_dir.root.canonicalize()?,
+    };
+    let run_log = if args.log {
+        let log = RunLog::create(&anek_dir.logs_dir())?;
+        eprintln!("{}: {:?}", "Logs".bright_blue(), log.dir());
+        Some(log)
+    } else {
+        None
+    };
+    let job = |i: usize, input: &CommandInputs, output: &JobOutput| -> Result<JobReport, Error> {
         if !args.pipable {
-            input.eprint_job(i + 1, total);
+            output.err(&input.job_header(i + 1, total));
+        }
+        if let Some(log) = &run_log {
+            output.log_to(File::create(log.job_file(input.index(), input.name()))?);
+            output.log(&format!(
+                "Job {} [{} of {}]: {}\n",
+                input.index(),
+                i + 1,
+                total,
+                input.name()
+            ));
         }
+        let mut report = JobReport::new(input.index(), input.name());
         let variables = run_utils::variables_from_input(input, &overwrite)?;
-        for cmd in &commands {
-            cmd.run(&variables, args.demo, args.pipable)?;
+        let key = Journal::job_key(&run_name, &commands, &selected, &variables, &variables_used);
+        let skip = match journal.succeeded(&key) {
+            Some(true) if args.resume || args.rerun_failed => Some("already succeeded"),
+            None if args.rerun_failed => Some("never run"),
+            _ => None,
+        };
+        if let Some(reason) = skip {
+            if !args.pipable {
+                output.err(&format!("{}: {}\n", "Skipped".yellow(), reason));
+            }
+            output.log(&format!("Skipped: {}\n", reason));
+            return Ok(report.skip(reason));
+        }
+        report.steps = if pipe {
+            run_piped(&commands, &selected, input, &variables, &opts, output)?
+        } else if let Some(deps) = &dependencies {
+            run_dag(&commands, deps, &selected, input, variables, &opts, output)?
+        } else {
+            run_steps(&commands, &selected, input, variables, &opts, output)?
+        };
+        if !(args.demo || args.pipable) {
+            journal.record(&key, report.failure().is_none(), input.name())?;
+        }
+        Ok(report)
+    };
+    let reports = jobs::run_parallel(
+        args.jobs,
+        args.keep_going,
+        &input_files,
+        mode,
+        |input| input.name().to_string(),
+        |i: usize, input: &CommandInputs, output: &JobOutput| -> Result<JobReport, Error> {
+            // errors fail the job, so they are in the summary and logs
+            Ok(job(i, input, output).unwrap_or_else(|e| {
+                output.err(&format!("{}: {}\n", "Error".bright_red(), e));
+                output.log(&format!("Error: {}\n", e));
+                JobReport::new(input.index(), input.name()).error(&e)
+            }))
+        },
+    )?;
+    if let Some(log) = &run_log {
+        log.write_index(&reports)?;
+    }
+    jobs::summarize(&reports, total)
+}
+
+/// Run the selected steps one after another, the steps after a
+/// failure are only run if they have the `@always` option
+fn run_steps(
+    commands: &[PipelineStep],
+    selected: &HashSet<usize>,
+    input: &CommandInputs,
+    mut variables: HashMap<String, String>,
+    opts: &RunOptions,
+    output: &JobOutput,
+) -> Result<Vec<StepReport>, Error> {
+    let mut reports: Vec<StepReport> = Vec::new();
+    for (i, cmd) in commands.iter().enumerate() {
+        let failed = reports
+            .iter()
+            .any(|s| !(s.status.success() || s.allow_failure));
+        if failed && !cmd.always {
+            continue;
+        }
+        let (steps, captured) =
+            run_pipeline_step(commands, i, selected, input, &variables, opts, output)?;
+        reports.extend(steps);
+        variables.extend(captured);
+    }
+    Ok(reports)
+}
+
+/// State of a step in a pipeline with dependencies
+#[derive(Clone, Copy, PartialEq)]
+enum StepState {
+    Waiting,
+    Running,
+    /// Finished, and whether the steps depending on it can run
+    Done(bool),
+}
+
+/// Run the selected steps as soon as the steps they depend on are
+/// done, so the independent steps run at the same time
+///
+/// Steps depending on a failed step are not run, unless they have
+/// the `@always` option. The captured variables are available to
+/// the steps that start after the capturing step is done.
+fn run_dag(
+    commands: &[PipelineStep],
+    dependencies: &[Vec<usize>],
+    selected: &HashSet<usize>,
+    input: &CommandInputs,
+    mut variables: HashMap<String, String>,
+    opts: &RunOptions,
+    output: &JobOutput,
+) -> Result<Vec<StepReport>, Error> {
+    let mut states = vec![StepState::Waiting; commands.len()];
+    let mut reports: Vec<StepReport> = Vec::new();
+    let mut error: Option<Error> = None;
+    thread::scope(|scope| {
+        let (tx, rx) = mpsc::channel();
+        // dropped once all the steps are started, so the receiver
+        // knows when no more results can come
+        let mut tx = Some(tx);
+        let mut running = 0;
+        loop {
+            let mut changed = error.is_none();
+            while changed {
+                changed = false;
+                for (i, cmd) in commands.iter().enumerate() {
+                    if states[i] != StepState::Waiting {
+                        continue;
+                    }
+                    let deps: Vec<StepState> = dependencies[i].iter().map(|&d| states[d]).collect();
+                    if !deps.iter().all(|s| matches!(s, StepState::Done(_))) {
+                        continue;
+                    }
+                    changed = true;
+                    if !(cmd.always || deps.iter().all(|s| *s == StepState::Done(true))) {
+                        states[i] = StepState::Done(false);
+                        continue;
+                    }
+                    let Some(tx) = tx.clone() else {
+                        continue;
+                    };
+                    states[i] = StepState::Running;
+                    running += 1;
+                    let variables = variables.clone();
+                    scope.spawn(move || {
+                        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                            run_pipeline_step(
+                                commands, i, selected, input, &variables, opts, output,
+                            )
+                        }))
+                        .unwrap_or_else(|_| {
+                            let name = commands[i].command.name();
+                            let step = StepReport::error(name, "panicked while running");
+                            Ok((vec![step], HashMap::new()))
+                        });
+                        tx.send((i, result)).ok();
+                    });
+                }
+            }
+            if error.is_some() || !states.contains(&StepState::Waiting) {
+                tx = None;
+            }
+            if running == 0 {
+                break;
+            }
+            let Ok((i, result)) = rx.recv() else {
+                // the threads of the running steps are gone without a result
+                for (i, state) in states.iter_mut().enumerate() {
+                    if *state == StepState::Running {
+                        *state = StepState::Done(false);
+                        reports.push(StepReport::error(
+                            commands[i].command.name(),
+                            "stopped without a result",
+                        ));
+                    }
+                }
+                break;
+            };
+            running -= 1;
+            match result {
+                Ok((steps, captured)) => {
+                    let ok = steps.iter().all(|s| s.status.success() || s.allow_failure);
+                    states[i] = StepState::Done(ok);
+                    reports.extend(steps);
+                    variables.extend(captured);
+                }
+                Err(e) => {
+                    states[i] = StepState::Done(false);
+                    error.get_or_insert(e);
+                }
+            }
+        }
+    });
+    match error {
+        Some(e) => Err(e),
+        None => Ok(reports),
+    }
+}
+
+/// Run a step of the pipeline if it is selected and its condition is
+/// true, returns its report (if any) and the variables it captured
+fn run_pipeline_step(
+    commands: &[PipelineStep],
+    i: usize,
+    selected: &HashSet<usize>,
+    input: &CommandInputs,
+    variables: &HashMap<String, String>,
+    opts: &RunOptions,
+    output: &JobOutput,
+) -> Result<(Vec<StepReport>, HashMap<String, String>), Error> {
+    let cmd = &commands[i];
+    let mut reports = Vec::new();
+    let mut captured = HashMap::new();
+    let step_variables =
+        match step_variables(i, cmd, selected, variables, opts, output, &mut reports)? {
+            Some(vars) => vars,
+            None => return Ok((reports, captured)),
+        };
+    let mut stdout = String::new();
+    let capture = cmd.capture.as_ref().map(|_| &mut stdout);
+    let mut step = run_step(&cmd.command, input, &step_variables, opts, output, capture)?;
+    step.allow_failure = cmd.allow_failure;
+    let failed = !step.status.success();
+    if failed && cmd.allow_failure {
+        output.err(&format!(
+            "{} ({}): continuing the job\n",
+            "Allowed failure".yellow(),
+            cmd.command.name()
+        ));
+    }
+    reports.push(step);
+    if (opts.demo || opts.pipable) && matches!(cmd.capture, Some(Capture::Variables)) {
+        // nothing was run, leave placeholders for the variables the
+        // steps use that are still unknown, so they can be shown
+        stdout = commands
+            .iter()
+            .flat_map(|c| {
+                c.variables()
+                    .into_iter()
+                    .filter(|v| !c.overwrite.contains_key(v))
+            })
+            .unique()
+            .filter(|v| !step_variables.contains_key(v))
+            .map(|v| format!("{}={{{}}}\n", v, v))
+            .collect();
+    }
+    if let (false, Some(capture)) = (failed, &cmd.capture) {
+        capture_variables(
+            cmd.command.name(),
+            capture,
+            &stdout,
+            &mut captured,
+            opts,
+            output,
+        )?;
+    }
+    Ok((reports, captured))
+}
+
+/// Variables for the step, or [`None`] if it is not selected or its
+/// condition is false, the skipped step is added to the `reports`
+fn step_variables(
+    i: usize,
+    cmd: &PipelineStep,
+    selected: &HashSet<usize>,
+    variables: &HashMap<String, String>,
+    opts: &RunOptions,
+    output: &JobOutput,
+    reports: &mut Vec<StepReport>,
+) -> Result<Option<HashMap<String, String>>, Error> {
+    if !selected.contains(&(i + 1)) {
+        if opts.demo {
+            output.err(&format!(
+                "{} ({}): not selected\n",
+                "Skipped".yellow(),
+                cmd.command.name()
+            ));
+        }
+        return Ok(None);
+    }
+    let step_variables = if cmd.overwrite.is_empty() {
+        variables.clone()
+    } else {
+        run_utils::overwrite_variables(variables, &cmd.overwrite)?
+    };
+    if let Some(cond) = &cmd.condition {
+        if !cond.check(&step_variables)? {
+            let reason = format!("{} is false", cond);
+            if !opts.pipable {
+                output.err(&format!(
+                    "{} ({}): {}\n",
+                    "Skipped".yellow(),
+                    cmd.command.name(),
+                    reason
+                ));
+            }
+            output.log(&format!("Skipped ({}): {}\n", cmd.command.name(), reason));
+            reports.push(StepReport {
+                command: cmd.command.name().to_string(),
+                status: StepStatus::Skipped(reason),
+                duration: Duration::ZERO,
+                attempts: 0,
+                allow_failure: cmd.allow_failure,
+            });
+            return Ok(None);
+        }
+    }
+    Ok(Some(step_variables))
+}
+
+/// Run the selected steps at once, connected with pipes
+///
+/// Steps can't capture their outputs, or be retried, and they are
+/// not checked for being up to date. The `@always` option has no use
+/// here as all the steps are run together.
+fn run_piped(
+    commands: &[PipelineStep],
+    selected: &HashSet<usize>,
+    input: &CommandInputs,
+    variables: &HashMap<String, String>,
+    opts: &RunOptions,
+    output: &JobOutput,
+) -> Result<Vec<StepReport>, Error> {
+    let mut reports = Vec::new();
+    let mut steps: Vec<(&PipelineStep, String, HashMap<String, String>)> = Vec::new();
+    for (i, cmd) in commands.iter().enumerate() {
+        if cmd.capture.is_some() {
+            return Err(Error::msg(format!(
+                "Step {} can't capture its output when the steps are piped",
+                cmd.command.name()
+            )));
+        }
+        if let Some(vars) = step_variables(i, cmd, selected, variables, opts, output, &mut reports)?
+        {
+            let rendered = cmd.command.render_for_shell(&vars, opts)?;
+            steps.push((cmd, rendered, vars));
+        }
+    }
+    if opts.pipable {
+        output.out(&format!(
+            "{}\n",
+            steps.iter().map(|(_, cmd, _)| cmd.as_str()).join(" | ")
+        ));
+    } else {
+        for (cmd, rendered, _) in &steps {
+            cmd.command.print(rendered, output);
+        }
+    }
+    let start = Instant::now();
+    let statuses = if opts.demo || opts.pipable || steps.is_empty() {
+        vec![StepStatus::Success; steps.len()]
+    } else {
+        // the script files are removed when these are dropped
+        let mut scripts = Vec::new();
+        let mut argvs = Vec::new();
+        for (cmd, rendered, vars) in &steps {
+            let (argv, script) = cmd.command.argv(rendered.to_string(), opts)?;
+            scripts.extend(script);
+            argvs.push((argv, cmd.command.env(input, vars, opts)));
+        }
+        let timeout = steps
+            .iter()
+            .filter_map(|(c, _, _)| c.command.timeout(opts))
+            .min();
+        if argvs.len() == 1 {
+            let (argv, env) = &argvs[0];
+            vec![output.run(argv, env, timeout, None)?]
+        } else {
+            output.run_piped(&argvs, timeout)?
+        }
+    };
+    let duration = start.elapsed();
+    for ((cmd, _, _), status) in steps.iter().zip(statuses) {
+        output.log(&format!("Status ({}): {}\n", cmd.command.name(), status));
+        if !status.success() {
+            output.err(&format!(
+                "{} ({}): {}{}\n",
+                "Failed".bright_red(),
+                cmd.command.name(),
+                status,
+                if cmd.allow_failure { " (allowed)" } else { "" }
+            ));
+        }
+        reports.push(StepReport {
+            command: cmd.command.name().to_string(),
+            status,
+            duration,
+            attempts: 1,
+            allow_failure: cmd.allow_failure,
+        });
+    }
+    Ok(reports)
+}
+
+/// Run the command for the job, retrying if it fails, unless its
+/// outputs are up to date
+fn run_step(
+    cmd: &Command,
+    input: &CommandInputs,
+    variables: &HashMap<String, String>,
+    opts: &RunOptions,
+    output: &JobOutput,
+    mut capture: Option<&mut String>,
+) -> Result<StepReport, Error> {
+    let mut step = StepReport {
+        command: cmd.name().to_string(),
+        status: StepStatus::UpToDate,
+        duration: Duration::ZERO,
+        attempts: 0,
+        allow_failure: false,
+    };
+    // the steps capturing the output need to run for the output
+    if !opts.force && capture.is_none() && cmd.up_to_date(variables)? {
+        if !opts.pipable {
+            output.err(&format!("{} ({})\n", "Up to date".yellow(), cmd.name()));
+        }
+        output.log(&format!("Up to date ({})\n", cmd.name()));
+        return Ok(step);
+    }
+    let retry = cmd.meta().retry.unwrap_or(opts.retry);
+    let start = Instant::now();
+    step.status = loop {
+        step.attempts += 1;
+        let status = cmd.run(input, variables, opts, output, capture.as_deref_mut())?;
+        output.log(&format!("Status: {} [{:?}]\n", status, start.elapsed()));
+        if status.success() || step.attempts > retry {
+            break status;
+        }
+        let delay = Duration::from_secs(1 << (step.attempts - 1).min(10));
+        output.err(&format!(
+            "{} ({}): {}, trying again in {:?} [{} of {}]\n",
+            "Retry".yellow(),
+            cmd.name(),
+            status,
+            delay,
+            step.attempts + 1,
+            retry + 1
+        ));
+        thread::sleep(delay);
+    };
+    step.duration = start.elapsed();
+    if !step.status.success() {
+        output.err(&format!(
+            "{} ({}): {}\n",
+            "Failed".bright_red(),
+            cmd.name(),
+            step.status
+        ));
+    } else if cmd.has_outputs() && !(opts.demo || opts.pipable) {
+        output.err(&format!("{} ({})\n", "Rebuilt".bright_green(), cmd.name()));
+    }
+    Ok(step)
+}
+
+/// Save the captured output of the step into the job's variables
+fn capture_variables(
+    name: &str,
+    capture: &Capture,
+    stdout: &str,
+    variables: &mut HashMap<String, String>,
+    opts: &RunOptions,
+    output: &JobOutput,
+) -> Result<(), Error> {
+    let mut captured: Vec<(String, String)> = Vec::new();
+    match capture {
+        // nothing was run, leave a placeholder so the later steps can be shown
+        Capture::Whole(var) if opts.demo || opts.pipable => {
+            captured.push((var.to_string(), format!("{{{}}}", var)))
+        }
+        Capture::Whole(var) => {
+            captured.push((var.to_string(), stdout.trim_end_matches('\n').to_string()))
+        }
+        Capture::Variables => {
+            for (i, line) in stdout.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let (k, v) = line.split_once('=').context(format!(
+                    "Invalid captured line# {} from {}: {:?}",
+                    i + 1,
+                    name,
+                    line
+                ))?;
+                captured.push((k.trim().to_string(), v.trim().to_string()));
+            }
+        }
+    }
+    for (var, value) in captured {
+        if !opts.pipable {
+            output.err(&format!(
+                "{} ({}): {}={}\n",
+                "Captured".bright_blue(),
+                name,
+                var,
+                value
+            ));
         }
+        output.log(&format!("Captured ({}): {}={}\n", name, var, value));
+        variables.insert(var, value);
     }
     Ok(())
 }
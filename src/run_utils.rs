@@ -1,13 +1,15 @@
 use anyhow::{Context, Error};
-use clap::{ArgGroup, Args, Subcommand, ValueHint};
+use clap::{Args, Subcommand, ValueHint};
 use itertools::Itertools;
 use number_range::NumberRangeOptions;
 use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
-use string_template_plus::{Render, RenderOptions, Template};
+use string_template_plus::Template;
 
-use crate::dtypes::{AnekDirectory, AnekDirectoryType, Command, CommandInputs};
-use crate::variable;
+use crate::dtypes::{
+    self, AnekDirectory, AnekDirectoryType, Command, CommandInputs, LoopConfig, Pipeline,
+    PipelineStep,
+};
+use crate::variable::{self, InputOptions, LoopValues};
 
 #[derive(Subcommand)]
 pub enum Inputs {
@@ -24,14 +26,15 @@ impl Inputs {
 }
 
 #[derive(Args, Clone)]
-#[command(group = ArgGroup::new("variables").required(false).multiple(false))]
 pub struct InputsArgs {
     /// Select subset to run, works in batch and loop only
     ///
     /// Since batch and loop are just a list of inputs to run, you can
     /// select a subset of them to run. The selection string needs to
     /// be comma separated values of possitive integers, you can have
-    /// range of values like: 1-5 to represent values from 1 to 5.
+    /// range of values like: 1-5 to represent values from 1 to 5. When
+    /// a loop is combined with a batch or inputs, the numbers are for
+    /// the combined list of jobs.
     #[arg(short, long, default_value="", value_hint = ValueHint::Other, value_parser=filter_index)]
     select_inputs: HashSet<usize>,
     /// Run from batch
@@ -41,14 +44,16 @@ pub struct InputsArgs {
     /// need to be relative to .anek/inputs/, run `anek list -i` for
     /// possible input files. If you provide more than a single batch
     /// file, their inputs will be combined and run one after another.
-    #[arg(short, long, group="variables", value_delimiter=',', value_hint = ValueHint::Other)]
+    #[arg(short, long, conflicts_with="input", value_delimiter=',', value_hint = ValueHint::Other)]
     batch: Vec<String>,
     /// Run commands by looping for the inputs
     ///
     /// Loops though the values of the input variables in the loop
     /// config and run the command templates on the combinations of
-    /// those different variables.
-    #[arg(short, long, group="variables", value_hint = ValueHint::Other)]
+    /// those different variables. With a batch or inputs, each of
+    /// those inputs is run with all the combinations, and the values
+    /// from the loop replace the ones from the inputs.
+    #[arg(short, long, value_hint = ValueHint::Other)]
     r#loop: Option<String>,
     /// Run from inputs
     ///
@@ -60,8 +65,18 @@ pub struct InputsArgs {
     /// you give multiple input files then it'll read them in order,
     /// if they're directory then each directory will be read in the
     /// aforementioned way.
-    #[arg(short, long, group="variables", value_delimiter=',', value_hint = ValueHint::Other)]
+    #[arg(short, long, value_delimiter=',', value_hint = ValueHint::Other)]
     input: Vec<String>,
+    /// Loop through these values of the variable
+    ///
+    /// Values given as `var=value1,value2,...` are looped through
+    /// like the variables in a loop, without having to make a loop
+    /// file for them. The values can also be a generator like in the
+    /// loop files (e.g. `mean=@range 10 20 5`). It can be repeated
+    /// for more variables, and it replaces the variable from the
+    /// loop if it is also in there.
+    #[arg(long, value_hint = ValueHint::Other, value_name = "VAR=VALUES")]
+    vary: Vec<String>,
     /// Overwrite input variables
     ///
     /// Provide variables to be overwritten in the input config. If
@@ -90,16 +105,298 @@ fn filter_index(inputs: &str) -> Result<HashSet<usize>, Error> {
         .collect())
 }
 
-pub fn cmd_from_pipeline(anek_dir: &AnekDirectory, pipeline: &str) -> Result<Vec<Command>, Error> {
+/// Number of the step (starting from 1) given its number or the
+/// name of its command, the first one if there are many
+fn step_number(commands: &[PipelineStep], step: &str) -> Result<usize, Error> {
+    if let Ok(n) = step.parse::<usize>() {
+        if n == 0 || n > commands.len() {
+            return Err(Error::msg(format!(
+                "Step {} out of range, there are {} steps",
+                n,
+                commands.len()
+            )));
+        }
+        return Ok(n);
+    }
+    commands
+        .iter()
+        .position(|c| c.command.name() == step)
+        .map(|i| i + 1)
+        .context(format!("No step with command {:?}", step))
+}
+
+/// Numbers of the steps to run (starting from 1)
+pub fn select_steps(
+    commands: &[PipelineStep],
+    from: Option<&str>,
+    to: Option<&str>,
+    only: Option<&str>,
+) -> Result<HashSet<usize>, Error> {
+    if let Some(only) = only {
+        let mut selected = HashSet::new();
+        for sel in only.split(',') {
+            if sel.chars().all(|c| c.is_ascii_digit() || c == '-') {
+                if let Some((a, b)) = sel.split_once('-') {
+                    if let (Ok(a), Ok(b)) = (a.parse::<usize>(), b.parse::<usize>()) {
+                        if a > b {
+                            return Err(Error::msg(format!(
+                                "Step range {:?} is reversed, use {}-{}",
+                                sel, b, a
+                            )));
+                        }
+                    }
+                }
+                selected.extend(filter_index(sel)?);
+            } else {
+                // all the steps with the command
+                let steps: Vec<usize> = commands
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, c)| c.command.name() == sel)
+                    .map(|(i, _)| i + 1)
+                    .collect();
+                if steps.is_empty() {
+                    return Err(Error::msg(format!("No step with command {:?}", sel)));
+                }
+                selected.extend(steps);
+            }
+        }
+        if let Some(n) = selected.iter().find(|&&n| n == 0 || n > commands.len()) {
+            return Err(Error::msg(format!(
+                "Step {} out of range, there are {} steps",
+                n,
+                commands.len()
+            )));
+        }
+        if selected.is_empty() {
+            return Err(Error::msg(format!("No steps selected by {:?}", only)));
+        }
+        return Ok(selected);
+    }
+    let first = from.map(|s| step_number(commands, s)).transpose()?;
+    let last = to.map(|s| step_number(commands, s)).transpose()?;
+    let (first, last) = (first.unwrap_or(1), last.unwrap_or(commands.len()));
+    if first > last {
+        return Err(Error::msg(format!(
+            "Step {:?} (#{}) is after step {:?} (#{}), no steps to run",
+            from.unwrap_or("1"),
+            first,
+            to.map_or_else(|| commands.len().to_string(), |s| s.to_string()),
+            last
+        )));
+    }
+    Ok((first..=last).collect())
+}
+
+/// Steps each step depends on (by their index), [`None`] if none of
+/// the steps use `@after`, i.e. they run one after another
+pub fn step_dependencies(commands: &[PipelineStep]) -> Result<Option<Vec<Vec<usize>>>, Error> {
+    if commands.iter().all(|c| c.after.is_empty()) {
+        return Ok(None);
+    }
+    let mut dependencies = Vec::new();
+    for cmd in commands {
+        let mut deps = Vec::new();
+        for id in &cmd.after {
+            let steps: Vec<usize> = commands
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| c.id() == id)
+                .map(|(i, _)| i)
+                .collect();
+            match steps.len() {
+                0 => {
+                    return Err(Error::msg(format!(
+                        "Step {} depends on unknown step {:?}",
+                        cmd.id(),
+                        id
+                    )))
+                }
+                1 => deps.push(steps[0]),
+                n => {
+                    return Err(Error::msg(format!(
+                        "{} steps have the id {:?}, use @id=<name> to tell them apart",
+                        n, id
+                    )))
+                }
+            }
+        }
+        dependencies.push(deps);
+    }
+    if let Some(cycle) = find_cycle(&dependencies) {
+        return Err(Error::msg(format!(
+            "Pipeline steps depend on themselves: {}",
+            cycle.iter().map(|&i| commands[i].id()).join(" -> ")
+        )));
+    }
+    Ok(Some(dependencies))
+}
+
+/// Find a cycle in the dependencies (indices of the items each item
+/// depends on), if there is one
+pub fn find_cycle(dependencies: &[Vec<usize>]) -> Option<Vec<usize>> {
+    fn visit(
+        i: usize,
+        dependencies: &[Vec<usize>],
+        visited: &mut [bool],
+        path: &mut Vec<usize>,
+    ) -> Option<Vec<usize>> {
+        if let Some(pos) = path.iter().position(|&p| p == i) {
+            let mut cycle = path[pos..].to_vec();
+            cycle.push(i);
+            return Some(cycle);
+        }
+        if visited[i] {
+            return None;
+        }
+        path.push(i);
+        for &d in &dependencies[i] {
+            if let Some(cycle) = visit(d, dependencies, visited, path) {
+                return Some(cycle);
+            }
+        }
+        path.pop();
+        visited[i] = true;
+        None
+    }
+    let mut visited = vec![false; dependencies.len()];
+    (0..dependencies.len()).find_map(|i| visit(i, dependencies, &mut visited, &mut Vec::new()))
+}
+
+/// Prefix for the command templates written in the pipeline files
+pub static PIPELINE_INLINE_PREFIX: &str = "$ ";
+/// Prefix to include the commands of another pipeline
+pub static PIPELINE_INCLUDE_PREFIX: &str = "@include ";
+
+/// What a line of the pipeline file has
+pub enum PipelineEntry {
+    /// Name of the command file, and the variables to overwrite
+    Command(String, HashMap<String, String>),
+    /// Name of the pipeline to include
+    Include(String),
+    /// Command template written in the pipeline itself
+    Inline(String),
+    /// Options for the whole pipeline (like `@pipe`), in a line by themselves
+    Header,
+}
+
+/// Line of a pipeline file
+pub struct PipelineLine {
+    pub number: usize,
+    pub entry: PipelineEntry,
+    /// Options for the step, like `@capture=var`
+    pub options: Vec<String>,
+}
+
+pub fn pipeline_lines(
+    anek_dir: &AnekDirectory,
+    pipeline: &str,
+) -> Result<Vec<PipelineLine>, Error> {
     variable::input_lines(
         &anek_dir.get_file(&AnekDirectoryType::Pipelines, &pipeline),
         None,
     )?
-    .iter()
-    .map(|(_, cmd)| -> Result<Command, Error> { anek_dir.command(cmd) })
+    .into_iter()
+    .map(|(number, line)| -> Result<PipelineLine, Error> {
+        if let Some(name) = line.strip_prefix(PIPELINE_INCLUDE_PREFIX) {
+            return Ok(PipelineLine {
+                number,
+                entry: PipelineEntry::Include(name.trim().to_string()),
+                options: vec![],
+            });
+        }
+        // options can be before the command, or the inline template
+        let mut options = Vec::new();
+        let mut rest = line.as_str();
+        while rest.starts_with(dtypes::STEP_OPTION_PREFIX) {
+            let (opt, r) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            options.push(opt.to_string());
+            rest = r.trim_start();
+        }
+        let entry = if rest.is_empty() {
+            PipelineEntry::Header
+        } else if let Some(templ) = rest.strip_prefix(PIPELINE_INLINE_PREFIX) {
+            PipelineEntry::Inline(templ.trim().to_string())
+        } else {
+            let mut args = dtypes::split_args(rest)?.into_iter();
+            let name = args.next().context(format!(
+                "No command in pipeline line# {}: {:?}",
+                number, line
+            ))?;
+            let mut overwrite = HashMap::new();
+            for arg in args {
+                if arg.starts_with(dtypes::STEP_OPTION_PREFIX) {
+                    options.push(arg);
+                } else {
+                    parse_overwrite(&arg, &mut overwrite)?;
+                }
+            }
+            PipelineEntry::Command(name, overwrite)
+        };
+        Ok(PipelineLine {
+            number,
+            entry,
+            options,
+        })
+    })
     .collect()
 }
 
+pub fn cmd_from_pipeline(anek_dir: &AnekDirectory, pipeline: &str) -> Result<Pipeline, Error> {
+    let mut commands = Pipeline::default();
+    pipeline_commands(anek_dir, pipeline, &mut Vec::new(), &mut commands)?;
+    Ok(commands)
+}
+
+/// Add the steps of the pipeline, `included` is the chain of
+/// pipelines that included this one, to detect the cycles. The
+/// headers of the included pipelines are ignored.
+fn pipeline_commands(
+    anek_dir: &AnekDirectory,
+    pipeline: &str,
+    included: &mut Vec<String>,
+    commands: &mut Pipeline,
+) -> Result<(), Error> {
+    if included.iter().any(|p| p == pipeline) {
+        return Err(Error::msg(format!(
+            "Pipeline includes itself: {} -> {}",
+            included.join(" -> "),
+            pipeline
+        )));
+    }
+    included.push(pipeline.to_string());
+    for line in pipeline_lines(anek_dir, pipeline)? {
+        let mut step = match line.entry {
+            PipelineEntry::Command(cmd, overwrite) => PipelineStep {
+                overwrite,
+                ..PipelineStep::new(anek_dir.command(&cmd)?)
+            },
+            PipelineEntry::Include(name) => {
+                pipeline_commands(anek_dir, &name, included, commands)?;
+                continue;
+            }
+            PipelineEntry::Inline(templ) => PipelineStep::new(Command::new(
+                format!("{}:{}", pipeline, line.number),
+                templ,
+            )?),
+            PipelineEntry::Header => {
+                if included.len() == 1 {
+                    for opt in &line.options {
+                        commands.set_header(opt)?;
+                    }
+                }
+                continue;
+            }
+        };
+        for opt in &line.options {
+            step.set_option(opt)?;
+        }
+        commands.steps.push(step);
+    }
+    included.pop();
+    Ok(())
+}
+
 pub fn command_args(args: &Inputs) -> Vec<(String, String)> {
     let args = args.on();
     args.command_args
@@ -118,98 +415,256 @@ pub fn overwrite_vars(
     command_args.iter().for_each(|(k, v)| {
         overwrite.insert(k.to_string(), v.to_string());
     });
-    if args.overwrite.len() > 0 {
-        for vars in &args.overwrite {
-            let mut split_data = vars.split(":").map(|s| s.split("=")).flatten();
-            overwrite.insert(
-                split_data
-                    .next()
-                    .context(format!("Invalid Variable in overwrite: {}", vars))?
-                    .to_string(),
-                split_data
-                    .next()
-                    .context(format!("Invalid Value in overwrite: {}", vars))?
-                    .to_string(),
-            );
-            while let Some(d) = split_data.next() {
-                eprintln!("Unused data from --overwrite: {}", d);
-            }
-        }
+    for vars in &args.overwrite {
+        parse_overwrite(vars, &mut overwrite)?;
     }
     Ok(overwrite)
 }
 
+/// Parse the `var=value` (or `var1=value1:var2=value2`) overwrite
+pub fn parse_overwrite(vars: &str, overwrite: &mut HashMap<String, String>) -> Result<(), Error> {
+    let mut split_data = vars.split(":").map(|s| s.split("=")).flatten();
+    overwrite.insert(
+        split_data
+            .next()
+            .context(format!("Invalid Variable in overwrite: {}", vars))?
+            .to_string(),
+        split_data
+            .next()
+            .context(format!("Invalid Value in overwrite: {}", vars))?
+            .to_string(),
+    );
+    while let Some(d) = split_data.next() {
+        eprintln!("Unused data from --overwrite: {}", d);
+    }
+    Ok(())
+}
+
 pub fn inputs(
     anek_dir: &AnekDirectory,
     args: &Inputs,
     variables: &HashSet<&str>,
+    opts: &InputOptions,
 ) -> Result<Vec<CommandInputs>, Error> {
-    if !args.on().batch.is_empty() {
-        input_files(anek_dir, &args.on().batch, &args.on().select_inputs)
-    } else if let Some(l) = &args.on().r#loop {
-        let overwrite = overwrite_vars(&args, &command_args(&args))?;
-        loop_inputs(
-            anek_dir,
-            &l,
-            &args.on().select_inputs,
-            &variables,
+    let args_on = args.on();
+    if args_on.r#loop.is_none() && args_on.vary.is_empty() {
+        return if !args_on.batch.is_empty() {
+            input_files(anek_dir, &args_on.batch, &args_on.select_inputs, opts)
+        } else {
+            Ok(vec![anek_dir.inputs(1, &args_on.input).read_files(opts)?])
+        };
+    }
+    let overwrite = overwrite_vars(args, &command_args(args))?;
+    let (loop_vars, config) = loop_variables(anek_dir, args_on)?;
+    let inputs = if !args_on.batch.is_empty() {
+        input_files(anek_dir, &args_on.batch, &HashSet::new(), opts)?
+    } else if !args_on.input.is_empty() {
+        vec![anek_dir.inputs(1, &args_on.input).read_files(opts)?]
+    } else {
+        return loop_inputs(
+            loop_vars,
+            &config,
+            &args_on.select_inputs,
+            variables,
             &overwrite,
-        )
+        );
+    };
+    // variables used through the values of the inputs, since the
+    // loop values are used when they are resolved
+    let values: Vec<&HashMap<String, String>> = inputs.iter().map(|inp| inp.variables()).collect();
+    let used = variable::used_variables(&values, variables.iter().map(|v| v.to_string()).collect());
+    let used: HashSet<&str> = used.iter().map(|v| v.as_str()).collect();
+    let loops = loop_inputs(loop_vars, &config, &HashSet::new(), &used, &overwrite)?;
+    Ok(inputs
+        .iter()
+        .cartesian_product(loops.iter())
+        .enumerate()
+        .map(|(i, inps)| (i + 1, inps))
+        .filter(|(i, _)| args_on.select_inputs.is_empty() || args_on.select_inputs.contains(i))
+        .map(|(i, (inp, lp))| inp.combine(i, lp))
+        .collect())
+}
+
+/// Values of the variables to loop through from the loop file and
+/// `--vary`, and the config of the loop file
+fn loop_variables(
+    anek_dir: &AnekDirectory,
+    args: &InputsArgs,
+) -> Result<(Vec<LoopValues>, LoopConfig), Error> {
+    let (mut loop_vars, config) = match &args.r#loop {
+        Some(loop_file) => {
+            let loop_dir = anek_dir
+                .get_directory(&AnekDirectoryType::Loops)
+                .join(format!("{loop_file}.d"));
+            (
+                variable::loop_inputs(&loop_dir)?,
+                LoopConfig::read(&loop_dir)?,
+            )
+        }
+        None => (Vec::new(), LoopConfig::default()),
+    };
+    for vary in &args.vary {
+        let values = vary_values(vary)?;
+        loop_vars.retain(|inps| inps[0].0 != values[0].0);
+        loop_vars.push(values);
+    }
+    Ok((loop_vars, config))
+}
+
+/// Parse the `var=value1,value2` values for `--vary`
+pub fn vary_values(vary: &str) -> Result<LoopValues, Error> {
+    let (var, values) = vary.split_once('=').context(format!(
+        "Invalid --vary {:?}, use var=value1,value2,...",
+        vary
+    ))?;
+    let values = if values.starts_with(variable::LOOP_GENERATOR_PREFIX) {
+        variable::loop_values(values)?
     } else {
-        Ok(vec![anek_dir.inputs(1, &args.on().input).read_files()?])
+        values.split(',').map(String::from).collect()
+    };
+    if values.is_empty() {
+        return Err(Error::msg(format!("No values for --vary {:?}", vary)));
     }
+    Ok(values
+        .into_iter()
+        .enumerate()
+        .map(|(i, v)| (var.to_string(), i + 1, v))
+        .collect())
 }
 
+/// Inputs for the combinations of the loop variables, only the ones
+/// used in the command are looped through
 pub fn loop_inputs(
-    anek_dir: &AnekDirectory,
-    loop_file: &str,
+    loop_vars: Vec<LoopValues>,
+    config: &LoopConfig,
     selection: &HashSet<usize>,
     variables: &HashSet<&str>,
     overwrite: &HashMap<String, String>,
 ) -> Result<Vec<CommandInputs>, Error> {
-    let loop_dir = anek_dir
-        .get_directory(&AnekDirectoryType::Loops)
-        .join(format!("{loop_file}.d"));
-    let loop_vars = variable::loop_inputs(&loop_dir)?;
+    // values of each variable, or of each zipped group, to combine
+    let mut groups: Vec<Vec<LoopValues>> = Vec::new();
+    for vars in &config.zip {
+        let values: Vec<&LoopValues> = vars
+            .iter()
+            .map(|v| {
+                loop_vars
+                    .iter()
+                    .find(|inps| &inps[0].0 == v)
+                    .context(format!("Zipped variable {:?} not in the loop", v))
+            })
+            .collect::<Result<_, Error>>()?;
+        if !values.iter().map(|v| v.len()).all_equal() {
+            return Err(Error::msg(format!(
+                "Zipped variables have different number of values: {}",
+                values
+                    .iter()
+                    .map(|v| format!("{} ({})", v[0].0, v.len()))
+                    .join(", ")
+            )));
+        }
+        groups.push(
+            (0..values[0].len())
+                .map(|i| values.iter().map(|v| v[i].clone()).collect())
+                .collect(),
+        );
+    }
+    for inps in loop_vars {
+        if !config.zip.iter().flatten().any(|v| v == &inps[0].0) {
+            groups.push(inps.into_iter().map(|inp| vec![inp]).collect());
+        }
+    }
 
-    let permutations = loop_vars
+    let conditions = config.variables();
+    let permutations = groups
         .into_iter()
-        // filter only the inputs used in the command file
-        .filter(|inps| variables.contains(&inps[0].0.as_str()))
-        .map(|inps| {
-            if let Some(value) = overwrite.get(inps[0].0.as_str()) {
-                // TODO, make overwrite metavariables work with loop too.
-                vec![(inps[0].0.clone(), 0, value.to_string())]
-            } else {
-                inps.to_vec()
-            }
+        // filter only the inputs used in the command file or the conditions
+        .filter(|grp| {
+            grp[0].iter().any(|(var, _, _)| {
+                variables.contains(var.as_str()) || conditions.contains(var.as_str())
+            })
+        })
+        .map(|grp| {
+            grp.into_iter()
+                .map(|inps| {
+                    inps.into_iter()
+                        .map(|(var, i, val)| match overwrite.get(var.as_str()) {
+                            // rendered for each combination below
+                            Some(value) => (var, 0, value.to_string()),
+                            None => (var, i, val),
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .unique()
+                .collect::<Vec<_>>()
         })
-        .multi_cartesian_product();
+        .multi_cartesian_product()
+        .map(|grps| grps.concat());
     let mut cmd_inputs = Vec::new();
-    for (li, inputs) in permutations.enumerate() {
-        let loop_index = li + 1;
+    let mut loop_index = 0;
+    for inputs in permutations {
+        let mut variables: HashMap<String, String> = inputs
+            .iter()
+            .filter(|(var, _, _)| !overwrite.contains_key(var))
+            .map(|(var, _, val)| (var.to_string(), val.to_string()))
+            .collect();
+        variables.insert("LOOP_INDEX".to_string(), (loop_index + 1).to_string());
+        // the overwritten loop variables are needed for the conditions
+        let (loop_overwrite, other_overwrite): (Vec<_>, Vec<_>) = overwrite
+            .iter()
+            .partition(|(var, _)| inputs.iter().any(|(v, _, _)| v == *var));
+        let mut rendered = render_overwrite(&variables, loop_overwrite)?;
+        let mut values = variables.clone();
+        values.extend(rendered.clone());
+        if !config.keep(&values)? {
+            continue;
+        }
+        rendered.extend(render_overwrite(&variables, other_overwrite)?);
+        values.extend(rendered.clone());
+        loop_index += 1;
 
         if selection.len() > 0 && !selection.contains(&loop_index) {
             continue;
         }
-        let mut variables: HashMap<String, String> = HashMap::new();
-        variables.insert("LOOP_INDEX".to_string(), loop_index.to_string());
         let mut name = String::new();
         for (var, i, val) in &inputs {
-            variables.insert(var.to_string(), val.to_string());
-            name.push_str(&format!("{} [{}]={}; ", &var, i, &val));
+            let val = values.get(var).unwrap_or(val);
+            name.push_str(&format!("{} [{}]={}; ", &var, i, val));
         }
 
         let inp = CommandInputs::from_variables(loop_index, name, variables);
-        cmd_inputs.push(inp);
+        cmd_inputs.push(inp.with_overwrite(rendered));
     }
     Ok(cmd_inputs)
 }
 
+/// Overwrite values rendered with the variables of the loop
+/// combination, the ones that use other variables (e.g. from the
+/// input files) are left out, they are rendered for the job later
+fn render_overwrite<'a>(
+    variables: &HashMap<String, String>,
+    overwrite: Vec<(&'a String, &'a String)>,
+) -> Result<HashMap<String, String>, Error> {
+    let renderop = dtypes::render_options(variables.clone());
+    let mut values = HashMap::new();
+    for (var, val) in overwrite {
+        let templ = Template::parse_template(val)?;
+        if templ
+            .parts()
+            .iter()
+            .flat_map(|p| p.variables())
+            .all(|v| variables.contains_key(v))
+        {
+            values.insert(var.to_string(), dtypes::render_template(&templ, &renderop)?);
+        }
+    }
+    Ok(values)
+}
+
 pub fn input_files(
     anek_dir: &AnekDirectory,
     batch_files: &Vec<String>,
     selection: &HashSet<usize>,
+    opts: &InputOptions,
 ) -> Result<Vec<CommandInputs>, Error> {
     batch_files
         .iter()
@@ -229,28 +684,92 @@ pub fn input_files(
         .map(|(i, line)| {
             let files: Vec<&str> = line.split(",").collect();
             let inp = anek_dir.inputs(i, &files);
-            inp.read_files()
+            inp.read_files(opts)
         })
         .collect()
 }
 
+/// Variables for the job from the input, with the overwrite applied
+///
+/// The input values using other variables are resolved after all the
+/// values are merged, so they use the values from the loop and the
+/// overwrite, and the overwrite values can use the resolved ones.
 pub fn variables_from_input(
     input: &CommandInputs,
     overwrite: &HashMap<String, String>,
 ) -> Result<HashMap<String, String>, Error> {
-    let mut input_map = input.variables().clone();
+    let mut variables = input.variables().clone();
+    variables.extend(input.overwrite().clone());
+    let mut unresolved: HashSet<String> = input
+        .unresolved()
+        .iter()
+        .filter(|v| !(input.overwrite().contains_key(*v) || overwrite.contains_key(*v)))
+        .cloned()
+        .collect();
+    let mut pending: Vec<(&String, Template)> = Vec::new();
+    let mut own: HashMap<String, String> = HashMap::new();
+    // the ones rendered for the loop are not rendered again
+    for (var, val) in overwrite {
+        if input.overwrite().contains_key(var) {
+            continue;
+        }
+        let templ = Template::parse_template(val)?;
+        let used: Vec<&str> = templ.parts().iter().flat_map(|p| p.variables()).collect();
+        if used.contains(&var.as_str()) {
+            // builds on the value it overwrites
+            own.insert(var.to_string(), val.to_string());
+        } else if used.is_empty() {
+            let renderop = dtypes::render_options(variables.clone());
+            variables.insert(var.to_string(), dtypes::render_template(&templ, &renderop)?);
+        } else {
+            pending.push((var, templ));
+        }
+    }
+    if !own.is_empty() {
+        let mut resolved = variables.clone();
+        let mut vars = unresolved.clone();
+        vars.extend(
+            input
+                .unresolved()
+                .intersection(&own.keys().cloned().collect())
+                .cloned(),
+        );
+        variable::resolve_inputs(&mut resolved, &vars)?;
+        let rendered = overwrite_variables(&resolved, &own)?;
+        variables.extend(own.into_keys().map(|k| (k.clone(), rendered[&k].clone())));
+    }
+    // the others are resolved along with the input values
+    for (var, templ) in &pending {
+        variables.insert(var.to_string(), templ.original().to_string());
+        unresolved.insert(var.to_string());
+    }
+    variable::resolve_inputs(&mut variables, &unresolved)?;
+    // the ones left unrendered (missing variables) are errors here
+    for (var, templ) in pending {
+        if variables[var] == templ.original() {
+            let renderop = dtypes::render_options(variables.clone());
+            let value = dtypes::render_template(&templ, &renderop)
+                .map_err(|e| Error::msg(format!("Overwrite {}: {}", var, e)))?;
+            variables.insert(var.to_string(), value);
+        }
+    }
+    Ok(variables)
+}
+
+/// Overwrite the variables, the values in `overwrite` can be
+/// metavariables that are rendered with the given variables
+pub fn overwrite_variables(
+    variables: &HashMap<String, String>,
+    overwrite: &HashMap<String, String>,
+) -> Result<HashMap<String, String>, Error> {
+    let mut input_map = variables.clone();
     // render the metavariables in the overwrite
-    let renderop = RenderOptions {
-        variables: input_map.clone(),
-        wd: PathBuf::default(),
-        shell_commands: true,
-    };
+    let renderop = dtypes::render_options(input_map.clone());
     let overwrite_meta: Vec<(String, String)> = overwrite
         .iter()
         .map(|(k, v)| -> Result<(String, String), Error> {
-            Template::parse_template(v)?
-                .render(&renderop)
-                .and_then(|s| Ok((k.to_string(), s)))
+            dtypes::render_template(&Template::parse_template(v)?, &renderop)
+                .map(|s| (k.to_string(), s))
         })
         .collect::<Result<Vec<(String, String)>, Error>>()?;
     for (k, v) in overwrite_meta {
@@ -1,9 +1,13 @@
 use anyhow::Error;
 use clap::{Args, ValueHint};
+use colored::Colorize;
+use itertools::Itertools;
+use std::collections::HashMap;
 use std::path::PathBuf;
 use string_template_plus::{Render, Template};
 
-use crate::dtypes::{AnekDirectory, AnekDirectoryType};
+use crate::dtypes::{self, AnekDirectory, AnekDirectoryType};
+use crate::variable::{self, InputOptions};
 
 #[derive(Args)]
 pub struct CliArgs {
@@ -13,13 +17,57 @@ pub struct CliArgs {
     /// the same ones from `anek list` command output
     #[arg(value_hint = ValueHint::Other)]
     anek_file: String,
+    /// Show the values of the input resolved
+    ///
+    /// Prints the sorted `key=value` pairs of the input file, with the
+    /// values that use other variables of the input resolved.
+    #[arg(short, long)]
+    resolved: bool,
     #[arg(default_value = ".", value_hint=ValueHint::DirPath)]
-    path: PathBuf,
+    pub path: PathBuf,
 }
 
-pub fn show_file(args: CliArgs) -> Result<(), Error> {
+pub fn show_file(args: CliArgs, input_opts: &InputOptions) -> Result<(), Error> {
     let filepath = AnekDirectory::from(&args.path)?.root.join(&args.anek_file);
 
+    if args
+        .anek_file
+        .starts_with(AnekDirectoryType::Loops.dir_name())
+        && filepath.is_file()
+        && filepath.file_name() != Some(dtypes::LOOP_CONFIG_FILE.as_ref())
+    {
+        // file as written, with the values of the generator lines
+        for (i, line) in std::fs::read_to_string(&filepath)?.lines().enumerate() {
+            let values = variable::loop_values(line.trim())
+                .map_err(|e| Error::msg(format!("{} in {:?} line# {}", e, filepath, i + 1)))?;
+            if values.len() == 1 && values[0] == line.trim() {
+                println!("{}", line);
+            } else {
+                println!("{} {} {}", line, "⇒".bright_blue(), values.join(" "));
+            }
+        }
+        return Ok(());
+    }
+    if args.resolved
+        && args
+            .anek_file
+            .starts_with(AnekDirectoryType::Inputs.dir_name())
+    {
+        // values as written, and resolved for the computed ones
+        let lines = variable::compact_lines_from_anek_file(&vec![filepath], input_opts)?;
+        let mut raw = HashMap::new();
+        variable::read_inputs(&lines, &mut raw)?;
+        let mut resolved = raw.clone();
+        variable::resolve_inputs(&mut resolved, &raw.keys().cloned().collect())?;
+        for (var, value) in raw.iter().sorted() {
+            if resolved[var] == *value {
+                println!("{}={}", var, value);
+            } else {
+                println!("{}={} {} {}", var, value, "⇒".bright_blue(), resolved[var]);
+            }
+        }
+        return Ok(());
+    }
     let contents = std::fs::read_to_string(filepath)?;
     if args
         .anek_file
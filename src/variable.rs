@@ -5,10 +5,12 @@ use itertools::Itertools;
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs::{read_dir, File};
 use std::io::{self, BufRead, BufReader, BufWriter, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use string_template_plus::{Render, RenderOptions, Template, TemplatePart};
 
-use crate::dtypes::{AnekDirectory, AnekDirectoryType};
+use crate::dtypes::{self, AnekDirectory, AnekDirectoryType};
+use crate::run_utils;
 
 #[derive(Args)]
 #[command(group = ArgGroup::new("list_info").required(false).multiple(false))]
@@ -40,6 +42,16 @@ pub struct CliArgs {
     /// Gives long description of the variable
     #[arg(short, long, group = "list_info", value_hint = ValueHint::Other, value_name="VAR")]
     info: Option<String>,
+    /// Explain where the value of the variable in the input comes from
+    ///
+    /// Prints the files and line numbers that define the variable in
+    /// the input given with --input, in the order they override each
+    /// other, and the final value.
+    #[arg(short, long, group = "list_info", requires = "input", value_hint = ValueHint::Other, value_name="VAR")]
+    explain: Option<String>,
+    /// Input to explain the variable for
+    #[arg(short = 'I', long, requires = "explain", value_delimiter = ',', value_hint = ValueHint::Other)]
+    input: Vec<String>,
     /// Update variables read from stdin in the given file
     ///
     /// Use it for generated files as it'll remove the comments. You
@@ -52,9 +64,12 @@ pub struct CliArgs {
     #[arg(short, long, value_hint = ValueHint::FilePath, value_parser=Template::parse_template)]
     update: Option<Template>,
     #[arg(default_value = ".", value_hint=ValueHint::DirPath)]
-    path: PathBuf,
+    pub path: PathBuf,
 }
 
+/// Lines of a file with their line numbers
+pub type NumberedLines = Vec<(usize, String)>;
+
 pub fn input_lines(
     filename: &PathBuf,
     renumber: Option<usize>,
@@ -166,6 +181,110 @@ pub fn read_inputs(
     Ok(())
 }
 
+/// The variables, and the ones used by their values (recursively)
+pub fn used_variables(
+    values: &[&HashMap<String, String>],
+    mut used: HashSet<String>,
+) -> HashSet<String> {
+    loop {
+        let more: HashSet<String> = values
+            .iter()
+            .flat_map(|map| map.iter())
+            .filter(|(var, _)| used.contains(*var))
+            .filter_map(|(_, val)| Template::parse_template(val).ok())
+            .flat_map(|t| {
+                t.parts()
+                    .iter()
+                    .flat_map(|p| p.variables())
+                    .map(|v| v.to_string())
+                    .collect::<Vec<String>>()
+            })
+            .filter(|v| !used.contains(v))
+            .collect();
+        if more.is_empty() {
+            return used;
+        }
+        used.extend(more);
+    }
+}
+
+/// Resolve the values that use other variables of the same input
+/// (e.g. `out_dir=results/{name}`), in the order of their dependencies
+///
+/// Only the variables in `vars` are resolved, the others (e.g. the
+/// loop values and overwrites) are used as they are. The values using
+/// variables that aren't in the input are left as they are, since
+/// they can't be resolved here.
+pub fn resolve_inputs(
+    input_map: &mut HashMap<String, String>,
+    vars: &HashSet<String>,
+) -> Result<(), Error> {
+    let computed: Vec<(String, Template)> = input_map
+        .iter()
+        .filter(|(k, _)| vars.contains(*k))
+        .filter_map(|(k, v)| {
+            let templ = Template::parse_template(v).ok()?;
+            let has_vars = templ.parts().iter().any(|p| !p.variables().is_empty());
+            has_vars.then(|| (k.to_string(), templ))
+        })
+        .sorted_by(|a, b| a.0.cmp(&b.0))
+        .collect();
+    let dependencies: Vec<Vec<usize>> = computed
+        .iter()
+        .map(|(_, templ)| {
+            let vars: Vec<&str> = templ.parts().iter().flat_map(|p| p.variables()).collect();
+            computed
+                .iter()
+                .enumerate()
+                .filter(|(_, (k, _))| vars.contains(&k.as_str()))
+                .map(|(i, _)| i)
+                .collect()
+        })
+        .collect();
+    // the value it overrides is gone by now, so it can't be used
+    if let Some((var, templ)) = computed
+        .iter()
+        .find(|(k, t)| t.parts().iter().flat_map(|p| p.variables()).any(|v| v == k))
+    {
+        return Err(Error::msg(format!(
+            "Input variable {} uses itself in {:?}, use a different name for the value it builds on",
+            var,
+            templ.original()
+        )));
+    }
+    if let Some(cycle) = run_utils::find_cycle(&dependencies) {
+        return Err(Error::msg(format!(
+            "Input variables depend on themselves: {}",
+            cycle.iter().map(|&i| &computed[i].0).join(" -> ")
+        )));
+    }
+    let mut pending: Vec<usize> = (0..computed.len()).collect();
+    while !pending.is_empty() {
+        let (ready, rest): (Vec<usize>, Vec<usize>) = pending
+            .iter()
+            .partition(|&&i| dependencies[i].iter().all(|d| !pending.contains(d)));
+        for i in ready {
+            let (var, templ) = &computed[i];
+            let complete = templ
+                .parts()
+                .iter()
+                .flat_map(|p| p.variables())
+                .all(|v| input_map.contains_key(v));
+            match dtypes::render_template(templ, &dtypes::render_options(input_map.clone())) {
+                Ok(value) => {
+                    input_map.insert(var.to_string(), value);
+                }
+                Err(e) if complete => {
+                    return Err(Error::msg(format!("Input variable {}: {}", var, e)))
+                }
+                Err(_) => (),
+            }
+        }
+        pending = rest;
+    }
+    Ok(())
+}
+
 pub fn list_files_sorted(filename: &PathBuf) -> Result<std::vec::IntoIter<PathBuf>, Error> {
     let files = read_dir(&filename)?;
     return Ok(files
@@ -238,25 +357,220 @@ pub fn list_anek_filenames(dirpath: &PathBuf) -> Result<Vec<String>, Error> {
     Ok(filenames)
 }
 
-pub fn loop_inputs(dirname: &PathBuf) -> Result<Vec<Vec<(String, usize, String)>>, Error> {
+/// Values of a loop variable as `(variable, index, value)`, the index
+/// starting from 1
+pub type LoopValues = Vec<(String, usize, String)>;
+
+pub fn loop_inputs(dirname: &PathBuf) -> Result<Vec<LoopValues>, Error> {
     let input_files = list_files_sorted(&dirname)?;
-    let mut input_values: Vec<Vec<(String, usize, String)>> = Vec::new();
+    let mut input_values: Vec<LoopValues> = Vec::new();
     for file in input_files {
         let filename = file.file_name().unwrap().to_str().unwrap().to_string();
-        let lines = input_lines(&file, Some(1))?;
-        if lines.len() == 0 {
+        if filename == dtypes::LOOP_CONFIG_FILE {
+            continue;
+        }
+        let values = loop_file_values(&file)?;
+        if values.is_empty() {
             continue;
         }
         input_values.push(
-            lines
+            values
                 .into_iter()
-                .map(|(i, l)| (filename.clone(), i, l))
+                .enumerate()
+                .map(|(i, v)| (filename.clone(), i + 1, v))
                 .collect(),
         );
     }
     Ok(input_values)
 }
 
+/// Prefix for the lines in the loop files that generate the values
+pub static LOOP_GENERATOR_PREFIX: &str = "@";
+
+/// Names of the generators, other lines starting with `@` are values
+pub static LOOP_GENERATORS: [&str; 4] = ["range", "linspace", "logspace", "glob"];
+
+/// Most values a generator line can make
+pub static MAX_LOOP_VALUES: usize = 1_000_000;
+
+/// Values in the loop file, with the generator lines expanded
+pub fn loop_file_values(file: &PathBuf) -> Result<Vec<String>, Error> {
+    let mut values = Vec::new();
+    for (i, line) in input_lines(file, None)? {
+        values.extend(
+            loop_values(&line)
+                .map_err(|e| Error::msg(format!("{} in {:?} line# {}", e, file, i)))?,
+        );
+    }
+    Ok(values)
+}
+
+/// Values from a line of the loop file, the line is the value itself
+/// unless it is a generator like `@range 1 10 2`; `@@` at the start
+/// is a literal `@`
+pub fn loop_values(line: &str) -> Result<Vec<String>, Error> {
+    let Some(gen) = line.strip_prefix(LOOP_GENERATOR_PREFIX) else {
+        return Ok(vec![line.to_string()]);
+    };
+    if gen.starts_with(LOOP_GENERATOR_PREFIX) {
+        return Ok(vec![gen.to_string()]);
+    }
+    let (name, args) = gen.split_once(char::is_whitespace).unwrap_or((gen, ""));
+    if !LOOP_GENERATORS.contains(&name) {
+        return Ok(vec![line.to_string()]);
+    }
+    let args: Vec<&str> = args.split_whitespace().collect();
+    let numbers = || -> Result<Vec<f64>, Error> {
+        args.iter()
+            .map(|a| {
+                a.parse::<f64>()
+                    .context(format!("Invalid number {:?} for @{}", a, name))
+            })
+            .collect()
+    };
+    let check_count = |count: usize| -> Result<usize, Error> {
+        if count > MAX_LOOP_VALUES {
+            Err(Error::msg(format!(
+                "@{} makes {} values, more than the limit of {}",
+                name, count, MAX_LOOP_VALUES
+            )))
+        } else {
+            Ok(count)
+        }
+    };
+    let is_integer = |a: &&str| {
+        let digits = a.strip_prefix(['-', '+']).unwrap_or(a);
+        !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+    };
+    let values = match (name, args.len()) {
+        ("range", 2 | 3) if args.iter().all(is_integer) => {
+            let nums = args
+                .iter()
+                .map(|a| {
+                    a.parse::<i64>().map_err(|e| {
+                        Error::msg(format!("Invalid integer {:?} for @range: {}", a, e))
+                    })
+                })
+                .collect::<Result<Vec<i64>, Error>>()?;
+            let step = nums.get(2).copied().unwrap_or(1);
+            if step == 0 {
+                return Err(Error::msg("Step for @range can't be zero"));
+            }
+            // i128 so the difference can't overflow
+            let count = ((nums[1] as i128 - nums[0] as i128) / step as i128 + 1).max(0);
+            let count = check_count(usize::try_from(count).unwrap_or(usize::MAX))?;
+            (0..count as i64)
+                .map(|i| (nums[0] + i * step).to_string())
+                .collect()
+        }
+        ("range", 2 | 3) => {
+            let nums = numbers()?;
+            let step = nums.get(2).copied().unwrap_or(1.0);
+            if step == 0.0 {
+                return Err(Error::msg("Step for @range can't be zero"));
+            }
+            // small tolerance so the end is included despite the rounding
+            let count = ((nums[1] - nums[0]) / step + 1e-9).floor() + 1.0;
+            if count.is_nan() {
+                return Err(Error::msg("Invalid numbers for @range"));
+            }
+            // saturating cast, so the huge ones are over the limit
+            let count = check_count(count.max(0.0) as usize)?;
+            (0..count)
+                .map(|i| format_number(range_value(nums[0], step, i)))
+                .collect()
+        }
+        ("linspace" | "logspace", 3) => {
+            let nums = numbers()?;
+            let count = args[2].parse::<usize>().map_err(|e| {
+                Error::msg(format!("Invalid count {:?} for @{}: {}", args[2], name, e))
+            })?;
+            let count = check_count(count)?;
+            let step = (nums[1] - nums[0]) / (count.max(2) - 1) as f64;
+            (0..count)
+                .map(|i| {
+                    let v = range_value(nums[0], step, i);
+                    format_number(if name == "logspace" { 10f64.powf(v) } else { v })
+                })
+                .collect()
+        }
+        ("glob", 1) => glob_files(args[0])?,
+        ("range", _) => return Err(Error::msg("Usage: @range <start> <end> [<step>]")),
+        ("linspace" | "logspace", _) => {
+            return Err(Error::msg(format!(
+                "Usage: @{} <start> <end> <count>",
+                name
+            )))
+        }
+        _ => return Err(Error::msg("Usage: @glob <pattern>")),
+    };
+    Ok(values)
+}
+
+/// Value of the range at the index, without the rounding noise
+/// around zero (like `5.5e-17` for `-0.3 + 3 * 0.1`)
+fn range_value(start: f64, step: f64, index: usize) -> f64 {
+    let value = start + index as f64 * step;
+    if value.abs() < step.abs() * 1e-9 {
+        0.0
+    } else {
+        value
+    }
+}
+
+/// Number without the floating point noise like `0.30000000000000004`,
+/// rounded to 12 significant digits so the small ones are kept
+fn format_number(num: f64) -> String {
+    let rounded: f64 = format!("{:.11e}", num).parse().unwrap_or(num);
+    if rounded == 0.0 {
+        // no `-0`
+        "0".to_string()
+    } else {
+        rounded.to_string()
+    }
+}
+
+/// Files matching the glob pattern (relative to the current
+/// directory), `*` and `?` match within a path component and `**`
+/// matches any number of them
+fn glob_files(pattern: &str) -> Result<Vec<String>, Error> {
+    let wildcard = pattern.find(['*', '?']).unwrap_or(pattern.len());
+    let base = match pattern[..wildcard].rfind('/') {
+        Some(i) => &pattern[..=i],
+        None => "",
+    };
+    let mut regex = String::from("^");
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    regex.push_str("(.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    let regex = regex::Regex::new(&regex)?;
+    let dir = PathBuf::from(if base.is_empty() { "." } else { base });
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+    Ok(list_filenames(&dir)?
+        .into_iter()
+        .map(|f| format!("{}{}", base, f))
+        .filter(|f| regex.is_match(f))
+        .sorted()
+        .collect())
+}
+
 fn print_variable_info(name: &str, path: &PathBuf, details: bool) -> Result<(), Error> {
     let file = File::open(path)?;
     print!("{} {:10}: ", "⇒".bright_blue(), name.green());
@@ -305,10 +619,14 @@ fn print_update(filename: &str, variable: &str, old: Option<&str>, new: &str) {
 fn update_file(file_s: &str, var_line: &str) -> Result<(), Error> {
     let file = PathBuf::from(file_s);
     if let Some((k, v)) = var_line.split_once("=") {
+        let mut includes: Vec<String> = Vec::new();
         let mut variables: HashMap<String, String> = if !file.exists() {
             HashMap::new()
         } else if file.is_file() {
-            let lines = input_lines(&file, None)?;
+            let (inc, lines): (NumberedLines, NumberedLines) = input_lines(&file, None)?
+                .into_iter()
+                .partition(|(_, l)| input_includes(l).is_some());
+            includes.extend(inc.into_iter().map(|(_, l)| l));
             let mut vars: HashMap<String, String> = HashMap::new();
             read_inputs(&lines, &mut vars)?;
             vars.iter()
@@ -323,6 +641,9 @@ fn update_file(file_s: &str, var_line: &str) -> Result<(), Error> {
         variables.insert(k.to_string(), v.to_string());
         let fp = std::fs::File::create(&file)?;
         let mut writer = BufWriter::new(fp);
+        for line in includes {
+            writeln!(writer, "{}", line)?;
+        }
         for k in variables.keys().sorted() {
             writeln!(writer, "{}={}", k, variables[k])?;
         }
@@ -369,11 +690,33 @@ fn update_from_stdin(file_templ: &Template) -> Result<(), Error> {
     Ok(())
 }
 
-pub fn compact_lines_from_anek_file(
+/// Prefixes for the lines in the input files that include the
+/// variables from other inputs
+pub static INPUT_INCLUDE_PREFIXES: [&str; 2] = ["@include ", "@extends "];
+
+/// Names of the inputs included in the line, if it is an include line
+pub fn input_includes(line: &str) -> Option<Vec<&str>> {
+    INPUT_INCLUDE_PREFIXES
+        .iter()
+        .find_map(|p| line.strip_prefix(p))
+        .map(|names| {
+            names
+                .split(',')
+                .map(|n| n.trim())
+                .filter(|n| !n.is_empty())
+                .collect()
+        })
+}
+
+/// Files for the anek file, the directory or the file and its `.d`
+/// directory
+fn anek_files(
     filenames: &Vec<PathBuf>,
-) -> Result<Vec<(usize, String)>, anyhow::Error> {
+    precedence: Option<Precedence>,
+) -> Result<Vec<PathBuf>, Error> {
     let mut files: Vec<PathBuf> = Vec::new();
     for filename in filenames {
+        let start = files.len();
         if filename.is_dir() {
             files.extend(list_filenames(&filename)?.iter().map(|f| filename.join(f)));
         } else if !filename.exists() || filename.is_file() {
@@ -397,18 +740,275 @@ pub fn compact_lines_from_anek_file(
                 filename
             )));
         }
+        if let Some(precedence) = precedence {
+            // the files read later override the ones before them
+            let parent = filename.parent().unwrap_or(filename);
+            let depth = |f: &PathBuf| f.strip_prefix(parent).map_or(0, |f| f.components().count());
+            match precedence {
+                Precedence::Inner => files[start..].sort_by_key(depth),
+                Precedence::Outer => files[start..].sort_by_key(|f| std::cmp::Reverse(depth(f))),
+            }
+        }
     }
-    let lines = files
-        .iter()
-        .map(|file| input_lines(&file, None))
-        .collect::<Result<Vec<Vec<(usize, String)>>, Error>>()?
+    Ok(files)
+}
+
+/// Directory the included inputs are relative to, the inputs
+/// directory the file is in
+fn inputs_dir(file: &Path) -> &Path {
+    file.ancestors()
+        .find(|p| {
+            p.file_name() == Some(AnekDirectoryType::Inputs.dir_name().as_ref())
+                && p.parent().and_then(|p| p.file_name()) == Some(".anek".as_ref())
+        })
+        .or(file.parent())
+        .unwrap_or(file)
+}
+
+/// Names of the inputs included by the anek file (not recursively)
+pub fn included_inputs(filenames: &Vec<PathBuf>) -> Result<Vec<String>, Error> {
+    let mut includes = Vec::new();
+    for file in anek_files(filenames, None)? {
+        for (_, line) in input_lines(&file, None)? {
+            if let Some(names) = input_includes(&line) {
+                includes.extend(names.into_iter().map(String::from));
+            }
+        }
+    }
+    Ok(includes)
+}
+
+pub fn compact_lines_from_anek_file(
+    filenames: &Vec<PathBuf>,
+    opts: &InputOptions,
+) -> Result<Vec<(usize, String)>, anyhow::Error> {
+    Ok(input_sources(filenames, opts)?
         .into_iter()
-        .flatten()
-        .collect::<Vec<(usize, String)>>();
-    Ok(lines)
+        .map(|src| (src.line, src.text))
+        .collect())
 }
 
-pub fn run_command(args: CliArgs) -> Result<(), Error> {
+/// Line of a merged input, and where it came from
+pub struct InputSource {
+    pub file: PathBuf,
+    pub line: usize,
+    pub text: String,
+    /// How deep the input is included, 0 for the input itself
+    pub depth: usize,
+}
+
+impl InputSource {
+    /// Variable defined in the line
+    pub fn variable(&self) -> Option<&str> {
+        self.text.split_once('=').map(|(k, _)| k)
+    }
+}
+
+/// Lines of the merged input with the files they came from
+pub fn input_sources(
+    filenames: &Vec<PathBuf>,
+    opts: &InputOptions,
+) -> Result<Vec<InputSource>, Error> {
+    let mut included = filenames.clone();
+    lines_with_includes(filenames, &mut included, opts.precedence)
+}
+
+/// Lines of the files, the lines from the included inputs come
+/// first so the values in the file itself override them
+fn lines_with_includes(
+    filenames: &Vec<PathBuf>,
+    included: &mut Vec<PathBuf>,
+    precedence: Option<Precedence>,
+) -> Result<Vec<InputSource>, Error> {
+    let mut includes: Vec<PathBuf> = Vec::new();
+    let mut lines: Vec<InputSource> = Vec::new();
+    for file in anek_files(filenames, precedence)? {
+        for (i, line) in input_lines(&file, None)? {
+            if let Some(names) = input_includes(&line) {
+                let dir = inputs_dir(&file);
+                includes.extend(names.into_iter().map(|n| dir.join(n)));
+            } else {
+                lines.push(InputSource {
+                    file: file.clone(),
+                    line: i,
+                    text: line,
+                    depth: 0,
+                });
+            }
+        }
+    }
+    let mut all_lines = Vec::new();
+    for inc in includes {
+        let name = |p: &PathBuf| {
+            p.strip_prefix(inputs_dir(p))
+                .unwrap_or(p)
+                .to_string_lossy()
+                .to_string()
+        };
+        if let Some(pos) = included.iter().position(|p| p == &inc) {
+            return Err(Error::msg(format!(
+                "Input includes itself: {} -> {}",
+                included[pos..].iter().map(name).join(" -> "),
+                name(&inc)
+            )));
+        }
+        if anek_files(&vec![inc.clone()], None)?.is_empty() {
+            return Err(Error::msg(format!(
+                "Included input {:?} not found",
+                name(&inc)
+            )));
+        }
+        included.push(inc.clone());
+        all_lines.extend(
+            lines_with_includes(&vec![inc], included, precedence)?
+                .into_iter()
+                .map(|src| InputSource {
+                    depth: src.depth + 1,
+                    ..src
+                }),
+        );
+        included.pop();
+    }
+    all_lines.extend(lines);
+    Ok(all_lines)
+}
+
+/// Which files override the others when a directory of inputs (or
+/// the `.d` directory of an input) defines a variable more than once
+#[derive(Clone, Copy)]
+pub enum Precedence {
+    /// Files inside the directories override the ones outside
+    Inner,
+    /// Files outside override the ones inside the directories
+    Outer,
+}
+
+impl Precedence {
+    pub fn parse(precedence: &str) -> Result<Self, Error> {
+        match precedence {
+            "inner" => Ok(Self::Inner),
+            "outer" => Ok(Self::Outer),
+            _ => Err(Error::msg(format!(
+                "Invalid input precedence {:?}, use inner or outer",
+                precedence
+            ))),
+        }
+    }
+}
+
+/// How the files of the inputs are merged
+#[derive(Default)]
+pub struct InputOptions {
+    /// Order of the files in the directories, [`None`] reads them in
+    /// the order they're listed (alphabetically, then the directories
+    /// inside)
+    pub precedence: Option<Precedence>,
+    /// Variables defined twice in the same input are an error instead
+    /// of a warning
+    pub strict: bool,
+    /// Files already warned about, so it's only done once
+    warned: Mutex<HashSet<PathBuf>>,
+}
+
+impl InputOptions {
+    pub fn new(precedence: Option<Precedence>, strict: bool) -> Self {
+        Self {
+            precedence,
+            strict,
+            warned: Mutex::new(HashSet::new()),
+        }
+    }
+}
+
+/// Warn (or error in strict mode) about the variables defined more
+/// than once in the merged input, overriding the values from the
+/// included inputs is fine
+pub fn check_duplicates(sources: &[InputSource], opts: &InputOptions) -> Result<(), Error> {
+    let mut defined: HashMap<&str, &InputSource> = HashMap::new();
+    // overridden variables for each file, in the order they're read
+    let mut overrides: Vec<(&PathBuf, Vec<String>)> = Vec::new();
+    // a line read again (from an input given twice) isn't a redefinition
+    let mut seen: HashSet<(&PathBuf, usize)> = HashSet::new();
+    for src in sources {
+        let Some(var) = src.variable() else {
+            continue;
+        };
+        if !seen.insert((&src.file, src.line)) {
+            continue;
+        }
+        if let Some(prev) = defined.insert(var, src) {
+            if prev.depth > src.depth {
+                continue;
+            }
+            let over = format!(
+                "{} (line# {}, from {:?} line# {})",
+                var, src.line, prev.file, prev.line
+            );
+            match overrides.iter_mut().find(|(f, _)| *f == &src.file) {
+                Some((_, vars)) => vars.push(over),
+                None => overrides.push((&src.file, vec![over])),
+            }
+        }
+    }
+    for (file, vars) in overrides {
+        let msg = format!(
+            "Variables in {:?} override the ones in the same input: {}",
+            file,
+            vars.join(", ")
+        );
+        if opts.strict {
+            return Err(Error::msg(msg));
+        }
+        if opts.warned.lock().unwrap().insert(file.clone()) {
+            eprintln!("{}: {}", "Warning".yellow(), msg);
+        }
+    }
+    Ok(())
+}
+
+/// Print the lines that define the variable in the input, in the
+/// order they override each other, and its final value
+fn explain_variable(
+    anek_dir: &AnekDirectory,
+    var: &str,
+    input: &Vec<String>,
+    opts: &InputOptions,
+) -> Result<(), Error> {
+    let sources = input_sources(&anek_dir.get_files(&AnekDirectoryType::Inputs, input), opts)?;
+    let lines: Vec<(usize, String)> = sources
+        .iter()
+        .map(|src| (src.line, src.text.to_string()))
+        .collect();
+    let mut variables = HashMap::new();
+    read_inputs(&lines, &mut variables)?;
+    let raw = variables.get(var).cloned();
+    // only the ones it needs, the others may run commands or fail
+    let vars = used_variables(&[&variables], HashSet::from([var.to_string()]));
+    resolve_inputs(&mut variables, &vars)?;
+    let mut found = false;
+    for src in sources.iter().filter(|s| s.variable() == Some(var)) {
+        let file = src.file.strip_prefix(&anek_dir.root).unwrap_or(&src.file);
+        println!(
+            "{} {:?} line# {}{}: {}",
+            if found { "overridden by" } else { "defined in" },
+            file,
+            src.line,
+            if src.depth > 0 { " (included)" } else { "" },
+            src.text
+        );
+        found = true;
+    }
+    match (raw, variables.get(var)) {
+        (Some(raw), Some(value)) if raw != *value => {
+            println!("{} {}={} (resolved)", "⇒".bright_blue(), var, value)
+        }
+        (_, Some(value)) => println!("{} {}={}", "⇒".bright_blue(), var, value),
+        _ => return Err(Error::msg(format!("Variable {} not in the input", var))),
+    }
+    Ok(())
+}
+
+pub fn run_command(args: CliArgs, opts: &InputOptions) -> Result<(), Error> {
     let anek_dir = AnekDirectory::from(&args.path)?;
     let mut vars: HashSet<&str> = HashSet::new();
     let inp_lines: Vec<Vec<(usize, String)>>;
@@ -419,7 +1019,15 @@ pub fn run_command(args: CliArgs) -> Result<(), Error> {
 
         inp_lines = files
             .iter()
-            .map(|file| input_lines(&file, None))
+            .map(|file| {
+                input_lines(file, None)
+                    .map(|lines| {
+                        lines
+                            .into_iter()
+                            .filter(|(_, l)| input_includes(l).is_none())
+                    })
+                    .map(|lines| lines.collect())
+            })
             .collect::<Result<Vec<Vec<(usize, String)>>, Error>>()?;
         inp_lines
             .iter()
@@ -465,6 +1073,8 @@ pub fn run_command(args: CliArgs) -> Result<(), Error> {
             &anek_dir.get_file(&AnekDirectoryType::Variables, &name),
             true,
         )?;
+    } else if let Some(var) = args.explain {
+        explain_variable(&anek_dir, &var, &args.input, opts)?;
     }
 
     if let Some(file) = args.update {
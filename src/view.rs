@@ -7,7 +7,7 @@ use crate::dtypes::AnekDirectory;
 #[derive(Args)]
 pub struct CliArgs {
     #[arg(default_value = ".", value_hint = ValueHint::DirPath)]
-    path: PathBuf,
+    pub path: PathBuf,
 }
 
 pub fn cmd(args: CliArgs) -> Result<(), Error> {